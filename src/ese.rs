@@ -1,11 +1,10 @@
-extern crate exitcode;
-use std::process;
-
 use itertools::Itertools;
+use log::{debug, error, info, warn};
 use simple_error::SimpleError;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::path::Path;
 
+use crate::record::{Record, RecordCollector};
 use crate::report::*;
 use crate::shared::*;
 use crate::utils::*;
@@ -16,6 +15,36 @@ use std::io::Write;
 
 const CACHE_SIZE_ENTRIES: usize = 10;
 
+// columns of SystemIndex_PropertyStore the reports are made of
+pub(crate) const ESE_REPORT_COLUMNS: &[&str] = &[
+    "System_ComputerName",
+    "WorkID",
+    // File Report
+    "System_ItemPathDisplay",
+    "System_DateModified",
+    "System_DateCreated",
+    "System_DateAccessed",
+    "System_Size",
+    "System_FileOwner",
+    "System_Search_AutoSummary",
+    "System_Search_GatherTime",
+    "System_ItemType",
+    // IE/Edge History Report
+    "System_ItemUrl",
+    "System_Link_TargetUrl",
+    "System_ItemDate",
+    "System_Title",
+    "System_Link_DateVisited",
+    // Activity History Report
+    "System_ItemNameDisplay",
+    "System_ActivityHistory_StartTime",
+    "System_ActivityHistory_EndTime",
+    "System_Activity_AppDisplayName",
+    "System_ActivityHistory_AppId",
+    "System_Activity_DisplayText",
+    "System_Activity_ContentUri",
+];
+
 fn prepare_selected_cols(cols: Vec<ColumnInfo>, sel_cols: &Vec<&str>) -> Vec<ColumnInfo> {
     let mut only_cols: Vec<ColumnInfo> = Vec::new();
     for c in cols {
@@ -36,7 +65,7 @@ fn prepare_selected_cols(cols: Vec<ColumnInfo>, sel_cols: &Vec<&str>) -> Vec<Col
                 }
             }
             if !found {
-                eprintln!("Requested column {i} not found in table columns");
+                debug!("Requested column {i} not found in table columns");
             }
         }
     }
@@ -99,14 +128,142 @@ pub fn ese_get_hostname(
     ))
 }
 
-pub fn ese_generate_report(
+fn ese_hostname_or_unknown(jdb: &dyn EseDb, table_id: u64, columns: &[ColumnInfo]) -> String {
+    match ese_get_hostname(jdb, table_id, columns) {
+        Ok(h) => h,
+        Err(e) => {
+            warn!("ese_get_hostname() failed: {e}. Will use '{UNKNOWN_HOSTNAME}' as a hostname.");
+            UNKNOWN_HOSTNAME.to_string()
+        }
+    }
+}
+
+// --raw: every column of every row of the selected tables, one report per table.
+pub fn ese_dump_raw(
     f: &Path,
     report_prod: &ReportProducer,
     status_logger: &mut Box<dyn Write>,
 ) -> Result<(), SimpleError> {
-    writeln!(status_logger, "Processing ESE db: {}", &f.to_string_lossy())
+    info!("Dumping ESE db: {}", &f.to_string_lossy());
+    let jdb = Box::new(
+        EseParser::load_from_path(CACHE_SIZE_ENTRIES, f)
+            .map_err(|e| SimpleError::new(format!("{e}")))?,
+    );
+    let edb_database_state = jdb.get_database_state();
+    let t = "SystemIndex_PropertyStore";
+    let recovered_hostname = match jdb.open_table(t) {
+        Ok(table_id) => {
+            let sel_cols = prepare_selected_cols(jdb.get_columns(t)?, &ESE_REPORT_COLUMNS.to_vec());
+            ese_hostname_or_unknown(&*jdb, table_id, &sel_cols)
+        }
+        Err(e) => {
+            warn!("Can't open {t}: {e}. Will use '{UNKNOWN_HOSTNAME}' as a hostname.");
+            UNKNOWN_HOSTNAME.to_string()
+        }
+    };
+    for t in jdb.get_tables()? {
+        if !report_prod.is_raw_table(&t) {
+            continue;
+        }
+        let table_id = jdb.open_table(&t)?;
+        let cols = jdb.get_columns(&t)?;
+        let mut rep = init_raw_report(
+            f,
+            report_prod,
+            &recovered_hostname,
+            &t,
+            status_logger,
+            Some(edb_database_state),
+        )?;
+        for c in &cols {
+            rep.set_field(&c.name);
+        }
+        let mut more = jdb.move_row(table_id, ESE_MoveFirst)?;
+        while more {
+            rep.create_new_row();
+            for c in &cols {
+                match jdb.get_column(table_id, c.id) {
+                    Ok(Some(v)) => ese_insert_raw_value(&*rep, c, &v),
+                    Ok(None) => {}
+                    Err(e) => warn!("Error while getting column {} from {}: {}", c.name, t, e),
+                }
+            }
+            if rep.is_full() {
+                break;
+            }
+            if is_interrupted() {
+                warn!(
+                    "Interrupted while reading '{}'. Its reports are partial.",
+                    f.to_string_lossy()
+                );
+                report_prod.mark_partial(f);
+                break;
+            }
+            more = match jdb.move_row(table_id, ESE_MoveNext) {
+                Ok(more) => more,
+                Err(e) => {
+                    error!(
+                        "Failed to read '{}': {e}. Its reports are partial.",
+                        f.to_string_lossy()
+                    );
+                    report_prod.mark_partial(f);
+                    false
+                }
+            };
+        }
+        rep.create_new_row();
+        rep.flush();
+    }
+    Ok(())
+}
+
+// Values by JET column type (JET_coltyp* in esent.h); what doesn't fit its type is binary.
+fn ese_insert_raw_value(r: &dyn Report, c: &ColumnInfo, v: &[u8]) {
+    let f = c.name.as_str();
+    match (c.typ, v.len()) {
+        (1, 1) => r.insert_bool_val(f, v[0] != 0),
+        (2, 1) => r.insert_int_val(f, v[0] as u64),
+        (3, 2) => r.insert_i64_val(f, i16::from_le_bytes([v[0], v[1]]) as i64),
+        (4, 4) => r.insert_i64_val(f, i32::from_le_bytes(v.try_into().unwrap()) as i64),
+        (5 | 15, 8) => r.insert_i64_val(f, i64::from_le_bytes(v.try_into().unwrap())),
+        (6, 4) => r.insert_float_val(f, f32::from_le_bytes(v.try_into().unwrap()) as f64),
+        (7, 8) => r.insert_float_val(f, f64::from_le_bytes(v.try_into().unwrap())),
+        (8, 8) => {
+            if let Some(dt) = ole_date_to_datetime(f64::from_le_bytes(v.try_into().unwrap())) {
+                r.insert_datetime_val(f, dt)
+            }
+        }
+        (10 | 12, _) if c.cp == 1200 => r.insert_str_val(f, from_utf16(v)),
+        (10 | 12, _) => r.insert_str_val(f, from_utf8(v)),
+        (14, 4) => r.insert_int_val(f, u32::from_le_bytes(v.try_into().unwrap()) as u64),
+        (17, 2) => r.insert_int_val(f, u16::from_le_bytes([v[0], v[1]]) as u64),
+        (9 | 11, _) if is_sid_column(f) && is_sid(v) => r.insert_sid_val(f, v),
+        _ => r.insert_blob_val(f, v),
+    }
+}
+
+// Tables of the database with their columns, for --list-tables.
+pub fn ese_list_tables(f: &Path) -> Result<Vec<(String, Vec<String>)>, SimpleError> {
+    let jdb = EseParser::load_from_path(CACHE_SIZE_ENTRIES, f)
         .map_err(|e| SimpleError::new(format!("{e}")))?;
-    let jdb = Box::new(EseParser::load_from_path(CACHE_SIZE_ENTRIES, f).unwrap());
+    let mut tables = Vec::new();
+    for t in jdb.get_tables()? {
+        let columns = jdb.get_columns(&t)?.into_iter().map(|c| c.name).collect();
+        tables.push((t, columns));
+    }
+    Ok(tables)
+}
+
+pub fn ese_generate_report(
+    f: &Path,
+    report_prod: &ReportProducer,
+    status_logger: &mut Box<dyn Write>,
+) -> Result<(), SimpleError> {
+    info!("Processing ESE db: {}", &f.to_string_lossy());
+    let jdb = Box::new(
+        EseParser::load_from_path(CACHE_SIZE_ENTRIES, f)
+            .map_err(|e| SimpleError::new(format!("{e}")))?,
+    );
     let edb_database_state = jdb.get_database_state();
     let t = "SystemIndex_PropertyStore";
     let table_id = jdb.open_table(t)?;
@@ -118,46 +275,10 @@ pub fn ese_generate_report(
     //let gather_table_fields = dump_file_gather_ese(f)?;
 
     // prepare to query only selected columns
-    let sel_cols = prepare_selected_cols(
-        cols,
-        &vec![
-            "System_ComputerName",
-            "WorkID",
-            // File Report
-            "System_ItemPathDisplay",
-            "System_DateModified",
-            "System_DateCreated",
-            "System_DateAccessed",
-            "System_Size",
-            "System_FileOwner",
-            "System_Search_AutoSummary",
-            "System_Search_GatherTime",
-            "System_ItemType",
-            // IE/Edge History Report
-            "System_ItemUrl",
-            "System_Link_TargetUrl",
-            "System_ItemDate",
-            "System_Title",
-            "System_Link_DateVisited",
-            // Activity History Report
-            "System_ItemNameDisplay",
-            "System_ActivityHistory_StartTime",
-            "System_ActivityHistory_EndTime",
-            "System_Activity_AppDisplayName",
-            "System_ActivityHistory_AppId",
-            "System_Activity_DisplayText",
-            "System_Activity_ContentUri",
-        ],
-    );
+    let sel_cols = prepare_selected_cols(cols, &ESE_REPORT_COLUMNS.to_vec());
 
     // get System_ComputerName value
-    let recovered_hostname = match ese_get_hostname(&*jdb, table_id, &sel_cols) {
-        Ok(h) => h,
-        Err(e) => {
-            eprintln!("ese_get_hostname() failed: {e}. Will use 'Unknown' as a hostname.");
-            "Unknown".to_string()
-        }
-    };
+    let recovered_hostname = ese_hostname_or_unknown(&*jdb, table_id, &sel_cols);
 
     let (mut file_rep, mut ie_rep, mut act_rep) = init_reports(
         f,
@@ -169,30 +290,7 @@ pub fn ese_generate_report(
 
     let mut h = HashMap::new();
     loop {
-        let mut workId: u32 = 0;
-        for c in &sel_cols {
-            if c.name == "WorkID" {
-                // INTEGER
-                match get_column::<u32>(&*jdb, table_id, c) {
-                    Ok(r) => {
-                        if let Some(wId) = r {
-                            workId = wId;
-                        }
-                    }
-                    Err(e) => eprintln!("Error while getting column {} from {}: {}", c.name, t, e),
-                }
-            } else {
-                match jdb.get_column(table_id, c.id) {
-                    Ok(r) => match r {
-                        None => {} //println!("Empty field: {}", c.name),
-                        Some(v) => {
-                            h.insert(c.name.clone(), v);
-                        }
-                    },
-                    Err(e) => eprintln!("Error while getting column {} from {}: {}", c.name, t, e),
-                }
-            }
-        }
+        let workId = ese_read_row(&*jdb, table_id, &sel_cols, &mut h);
         let ie_history = ese_IE_history_record(&mut *ie_rep, workId, &h);
         let act_history = ese_activity_history_record(&mut *act_rep, workId, &h);
         if !ie_history && !act_history {
@@ -200,14 +298,35 @@ pub fn ese_generate_report(
         }
         h.clear();
 
-        if !jdb.move_row(table_id, ESE_MoveNext)? {
+        if file_rep.is_full() && ie_rep.is_full() && act_rep.is_full() {
+            break;
+        }
+        if is_interrupted() {
+            warn!(
+                "Interrupted while reading '{}'. Its reports are partial.",
+                f.to_string_lossy()
+            );
+            report_prod.mark_partial(f);
             break;
         }
+        match jdb.move_row(table_id, ESE_MoveNext) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                // keep what was read so far, the reports are finalized when dropped
+                error!(
+                    "Failed to read '{}': {e}. Its reports are partial.",
+                    f.to_string_lossy()
+                );
+                report_prod.mark_partial(f);
+                break;
+            }
+        }
     }
     if report_prod.is_db_dirty(Some(edb_database_state)) {
         if report_prod.get_report_type() == ReportOutput::ToStdout {
-            eprintln!("WARNING: The database state is not clean");
-            process::exit(exitcode::DATAERR)
+            // stdout has no file name to flag it, the run fails instead (its records are written)
+            return Err(SimpleError::new("The database state is not clean"));
         } else {
             eprintln!("WARNING: The database state is not clean.");
             eprintln!(
@@ -220,6 +339,105 @@ pub fn ese_generate_report(
     Ok(())
 }
 
+// The selected columns of the current row into h, returns its WorkID.
+fn ese_read_row(
+    jdb: &dyn EseDb,
+    table_id: u64,
+    sel_cols: &[ColumnInfo],
+    h: &mut HashMap<String, Vec<u8>>,
+) -> u32 {
+    let t = "SystemIndex_PropertyStore";
+    let mut workId: u32 = 0;
+    for c in sel_cols {
+        if c.name == "WorkID" {
+            // INTEGER
+            match get_column::<u32>(jdb, table_id, c) {
+                Ok(r) => {
+                    if let Some(wId) = r {
+                        workId = wId;
+                    }
+                }
+                Err(e) => warn!("Error while getting column {} from {}: {}", c.name, t, e),
+            }
+        } else {
+            match jdb.get_column(table_id, c.id) {
+                Ok(r) => match r {
+                    None => {} //println!("Empty field: {}", c.name),
+                    Some(v) => {
+                        h.insert(c.name.clone(), v);
+                    }
+                },
+                Err(e) => warn!("Error while getting column {} from {}: {}", c.name, t, e),
+            }
+        }
+    }
+    workId
+}
+
+// The records ese_records reads, a row at a time.
+pub struct EseRecords {
+    jdb: Box<dyn EseDb>,
+    table_id: u64,
+    sel_cols: Vec<ColumnInfo>,
+    more: bool,                // the cursor is on a row not read yet
+    pending: VecDeque<Record>, // a row can make a record of several reports
+}
+
+// The records of the reports of a Windows.edb, as ese_generate_report writes them
+// but without any ReportProducer options applied.
+pub fn ese_records(f: &Path) -> Result<EseRecords, SimpleError> {
+    let jdb: Box<dyn EseDb> = Box::new(
+        EseParser::load_from_path(CACHE_SIZE_ENTRIES, f)
+            .map_err(|e| SimpleError::new(format!("{e}")))?,
+    );
+    let t = "SystemIndex_PropertyStore";
+    let table_id = jdb.open_table(t)?;
+    let sel_cols = prepare_selected_cols(jdb.get_columns(t)?, &ESE_REPORT_COLUMNS.to_vec());
+    let more = jdb.move_row(table_id, ESE_MoveFirst)?;
+    Ok(EseRecords {
+        jdb,
+        table_id,
+        sel_cols,
+        more,
+        pending: VecDeque::new(),
+    })
+}
+
+impl Iterator for EseRecords {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Record> {
+        while self.pending.is_empty() && self.more {
+            let mut h = HashMap::new();
+            let work_id = ese_read_row(&*self.jdb, self.table_id, &self.sel_cols, &mut h);
+            let mut ie = RecordCollector::default();
+            let mut act = RecordCollector::default();
+            let ie_history = ese_IE_history_record(&mut ie, work_id, &h);
+            let act_history = ese_activity_history_record(&mut act, work_id, &h);
+            if ie_history {
+                self.pending.push_back(ie.into_record(ReportKind::Internet));
+            }
+            if act_history {
+                self.pending
+                    .push_back(act.into_record(ReportKind::Activity));
+            }
+            if !ie_history && !act_history {
+                let mut file = RecordCollector::default();
+                ese_dump_file_record(&mut file, work_id, &h);
+                self.pending.push_back(file.into_record(ReportKind::File));
+            }
+            self.more = match self.jdb.move_row(self.table_id, ESE_MoveNext) {
+                Ok(more) => more,
+                Err(e) => {
+                    error!("Failed to read the next row: {e}");
+                    false
+                }
+            };
+        }
+        self.pending.pop_front()
+    }
+}
+
 // File Report
 fn ese_dump_file_record(r: &mut dyn Report, workId: u32, h: &HashMap<String, Vec<u8>>) {
     r.create_new_row();
@@ -228,25 +446,13 @@ fn ese_dump_file_record(r: &mut dyn Report, workId: u32, h: &HashMap<String, Vec
         let csp = column_string_part(col);
         match csp {
             "System_ItemPathDisplay" => r.insert_str_val(csp, from_utf16(val)),
-            "System_DateModified" => r.insert_str_val(
-                csp,
-                format_date_time(get_date_time_from_filetime(u64::from_bytes(val))),
-            ),
-            "System_DateCreated" => r.insert_str_val(
-                csp,
-                format_date_time(get_date_time_from_filetime(u64::from_bytes(val))),
-            ),
-            "System_DateAccessed" => r.insert_str_val(
-                csp,
-                format_date_time(get_date_time_from_filetime(u64::from_bytes(val))),
-            ),
+            "System_DateModified" => insert_filetime_val(r, csp, u64::from_bytes(val)),
+            "System_DateCreated" => insert_filetime_val(r, csp, u64::from_bytes(val)),
+            "System_DateAccessed" => insert_filetime_val(r, csp, u64::from_bytes(val)),
             "System_Size" => r.insert_int_val(csp, u64::from_bytes(val)),
             "System_FileOwner" => r.insert_str_val(csp, from_utf16(val)),
             "System_Search_AutoSummary" => r.insert_str_val(csp, from_utf16(val)),
-            "System_Search_GatherTime" => r.insert_str_val(
-                csp,
-                format_date_time(get_date_time_from_filetime(u64::from_bytes(val))),
-            ),
+            "System_Search_GatherTime" => insert_filetime_val(r, csp, u64::from_bytes(val)),
             "System_ItemType" => r.insert_str_val(csp, from_utf16(val)),
             "System_ComputerName" => r.insert_str_val(csp, from_utf16(val)),
             // "ScopeID" => println!("{}: {}", col, i32::from_bytes(val)),
@@ -301,25 +507,13 @@ fn ese_IE_history_record(r: &mut dyn Report, workId: u32, h: &HashMap<String, Ve
     for (col, val) in h.iter().sorted() {
         let csp = column_string_part(col);
         match csp {
-            "System_DateModified" => r.insert_str_val(
-                csp,
-                format_date_time(get_date_time_from_filetime(u64::from_bytes(val))),
-            ),
+            "System_DateModified" => insert_filetime_val(r, csp, u64::from_bytes(val)),
             "System_ItemUrl" => r.insert_str_val(csp, from_utf16(val)),
             "System_Link_TargetUrl" => r.insert_str_val(csp, from_utf16(val)),
-            "System_ItemDate" => r.insert_str_val(
-                csp,
-                format_date_time(get_date_time_from_filetime(u64::from_bytes(val))),
-            ),
-            "System_Search_GatherTime" => r.insert_str_val(
-                csp,
-                format_date_time(get_date_time_from_filetime(u64::from_bytes(val))),
-            ),
+            "System_ItemDate" => insert_filetime_val(r, csp, u64::from_bytes(val)),
+            "System_Search_GatherTime" => insert_filetime_val(r, csp, u64::from_bytes(val)),
             "System_Title" => r.insert_str_val(csp, from_utf16(val)),
-            "System_Link_DateVisited" => r.insert_str_val(
-                csp,
-                format_date_time(get_date_time_from_filetime(u64::from_bytes(val))),
-            ),
+            "System_Link_DateVisited" => insert_filetime_val(r, csp, u64::from_bytes(val)),
             "System_ComputerName" => r.insert_str_val(csp, from_utf16(val)),
             _ => {}
         }
@@ -351,14 +545,8 @@ fn ese_activity_history_record(
         match csp {
             "System_ItemNameDisplay" => r.insert_str_val(csp, from_utf16(val)),
             "System_ItemUrl" => r.insert_str_val(csp, from_utf16(val)), // TODO: get UserSID from here
-            "System_ActivityHistory_StartTime" => r.insert_str_val(
-                csp,
-                format_date_time(get_date_time_from_filetime(u64::from_bytes(val))),
-            ),
-            "System_ActivityHistory_EndTime" => r.insert_str_val(
-                csp,
-                format_date_time(get_date_time_from_filetime(u64::from_bytes(val))),
-            ),
+            "System_ActivityHistory_StartTime" => insert_filetime_val(r, csp, u64::from_bytes(val)),
+            "System_ActivityHistory_EndTime" => insert_filetime_val(r, csp, u64::from_bytes(val)),
             "System_Activity_AppDisplayName" => r.insert_str_val(csp, from_utf16(val)),
             "System_ActivityHistory_AppId" => r.insert_str_val(csp, from_utf16(val)),
             "System_Activity_DisplayText" => r.insert_str_val(csp, from_utf16(val)),
@@ -374,3 +562,17 @@ fn ese_activity_history_record(
     }
     true
 }
+
+#[test]
+fn test_ese_records() {
+    let records: Vec<Record> = ese_records(Path::new("tests/testdata/Windows.edb"))
+        .unwrap()
+        .collect();
+    for kind in [ReportKind::File, ReportKind::Internet, ReportKind::Activity] {
+        assert!(
+            records.iter().any(|r| r.kind == kind),
+            "no {kind:?} records"
+        );
+    }
+    assert!(records.iter().all(|r| r.get("WorkId").is_some()));
+}
@@ -1,9 +1,23 @@
 #![allow(non_upper_case_globals)]
+#[allow(non_snake_case)]
+pub mod ese;
+pub mod record;
 #[warn(non_camel_case_types)]
 pub mod report;
+#[allow(non_snake_case)]
+pub mod scan;
+pub mod shared;
+#[allow(non_snake_case)]
+pub mod sqlite;
 #[allow(non_camel_case_types)]
 pub mod utils;
 
+pub use ese::{ese_generate_report, ese_records};
+pub use record::{Record, RecordHook};
+pub use report::{Report, ReportProducer};
+pub use scan::{process_directory, DatabaseReport, ProcessOptions, RunReport};
+pub use sqlite::{sqlite_generate_report, sqlite_records};
+
 use crate::utils::column_string_part;
 use ::function_name::named;
 use log::{debug, error, info, trace};
@@ -545,9 +559,8 @@ impl<'a> FieldReader for SqlReader<'a> {
 }
 
 //--------------------------------------------------------------------
-use crate::report::{ReportFormat, ReportOutput, ReportProducer};
+use crate::report::{ReportFormat, ReportOutput};
 use evalexpr::{Context, ContextWithMutableVariables, IterateVariablesContext, Value};
-use report::Report;
 use std::path::Path;
 
 #[derive(Debug)]
@@ -561,10 +574,18 @@ struct ReportColumn {
 }
 
 impl<R: Report + ?Sized> Report for Box<R> {
+    fn footer(&mut self) {
+        (**self).footer()
+    }
+
     fn create_new_row(&mut self) {
         (**self).create_new_row()
     }
 
+    fn flush(&mut self) {
+        (**self).flush()
+    }
+
     fn insert_str_val(&self, f: &str, s: String) {
         (**self).insert_str_val(f, s)
     }
@@ -573,6 +594,42 @@ impl<R: Report + ?Sized> Report for Box<R> {
         (**self).insert_int_val(f, n)
     }
 
+    fn insert_i64_val(&self, f: &str, n: i64) {
+        (**self).insert_i64_val(f, n)
+    }
+
+    fn insert_bool_val(&self, f: &str, b: bool) {
+        (**self).insert_bool_val(f, b)
+    }
+
+    fn insert_float_val(&self, f: &str, v: f64) {
+        (**self).insert_float_val(f, v)
+    }
+
+    fn insert_datetime_val(&self, f: &str, dt: DateTime<Utc>) {
+        (**self).insert_datetime_val(f, dt)
+    }
+
+    fn insert_blob_val(&self, f: &str, b: &[u8]) {
+        (**self).insert_blob_val(f, b)
+    }
+
+    fn insert_sid_val(&self, f: &str, b: &[u8]) {
+        (**self).insert_sid_val(f, b)
+    }
+
+    fn is_full(&self) -> bool {
+        (**self).is_full()
+    }
+
+    fn write_error(&self) -> Option<std::io::ErrorKind> {
+        (**self).write_error()
+    }
+
+    fn set_field(&self, f: &str) {
+        (**self).set_field(f)
+    }
+
     fn is_some_val_in_record(&self) -> bool {
         (**self).is_some_val_in_record()
     }
@@ -605,7 +662,8 @@ pub fn do_reports(
         OutputType::ToFile => ReportOutput::ToFile,
     };
 
-    let rep_factory = ReportProducer::new(cfg.output_dir.as_ref(), report_format, report_type);
+    let rep_factory =
+        ReportProducer::new(cfg.output_dir.as_ref(), report_format, report_type).unwrap();
     let mut cached = HashMap::<String, String>::new();
 
     for report in &cfg.reports {
@@ -858,9 +916,7 @@ pub fn do_reports(
                     }
                     ColumnType::DateTime => {
                         if let Some(dt) = reader.get_datetime(col_id) {
-                            report
-                                .reporter
-                                .insert_str_val(col.title.as_str(), utils::format_date_time(dt));
+                            report.reporter.insert_datetime_val(col.title.as_str(), dt);
                         }
                     }
                     ColumnType::GUID => {
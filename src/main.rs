@@ -1,81 +1,23 @@
-#![allow(non_upper_case_globals, non_snake_case, non_camel_case_types)]
-
-extern crate bitflags;
-
-use clap::Parser;
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
+use log::{warn, LevelFilter};
+use regex::Regex;
 
 use std::fs;
-use std::io::Write;
+use std::io;
 use std::path::PathBuf;
+use std::process;
+use std::thread;
 
 use simple_error::SimpleError;
 
-pub mod ese;
-pub mod report;
-pub mod shared;
-pub mod sqlite;
-pub mod utils;
-
-use crate::ese::*;
-use crate::report::*;
-use crate::sqlite::*;
-
-fn dump(
-    input_dir: &PathBuf,
-    report_prod: &ReportProducer,
-    status_logger: &mut Box<dyn Write>,
-) -> Result<(), SimpleError> {
-    let mut processed = 0;
-    match fs::read_dir(input_dir) {
-        Ok(dir) => {
-            for entry in dir.flatten() {
-                let p = entry.path();
-                let metadata = fs::metadata(&p).unwrap();
-                if metadata.is_dir() {
-                    dump(&p, report_prod, status_logger)?;
-                } else if is_valid_file(&p) {
-                    processed += 1;
-                    let ext = p.extension().and_then(|e| e.to_str()).map(|s| s.to_lowercase());
-                    let _ = match ext.as_deref() {
-                        Some("edb") => ese_generate_report(&p, report_prod, status_logger),
-                        Some("db") => sqlite_generate_report(&p, report_prod, status_logger),
-                        _ => continue,
-                    };
-                }
-            }
-        }
-        Err(e) => {
-            panic!(
-                "Could not read dir '{}': {e}",
-                input_dir
-                    .to_str()
-                    .unwrap_or_else(|| "Could not print dir name")
-            )
-        }
-    }
-    if processed > 0 {
-        writeln!(
-            status_logger,
-            "\nFound {} Windows Search database(s)",
-            &processed.to_string()
-        )
-        .map_err(|e| SimpleError::new(format!("{e}")))
-        .unwrap();
-    }
-
-    Ok(())
-}
-
-fn is_valid_file(p: &PathBuf) -> bool {
-    let is_valid_name = p.file_stem()
-        .and_then(|s| s.to_str())
-        .map_or(false, |name| name.eq_ignore_ascii_case("windows") || name.to_ascii_lowercase().starts_with("s-1-"));
-    let is_valid_ext = p.extension()
-        .and_then(|e| e.to_str())
-        .map_or(false, |ext| ext.to_ascii_lowercase() == "edb" || ext.to_ascii_lowercase() == "db");
-    is_valid_name && is_valid_ext
-}
-
+use wsa_lib::record::{is_known_field, BlobEncoding, NoTimestampPolicy, Timezone};
+use wsa_lib::report::{
+    ClobberPolicy, FilenameTemplate, ReportArchive, ReportCompression, ReportFormat, ReportKind,
+    ReportOutput, ReportProducer,
+};
+use wsa_lib::scan::{expand_input, find_databases, list_tables, write_reports, ScanOptions};
+use wsa_lib::shared::{interrupt, is_interrupted};
 
 /// Copyright 2023, Aon
 ///
@@ -90,26 +32,29 @@ fn is_valid_file(p: &PathBuf) -> bool {
 ///
 /// will scan the C:\test directory for Windows.db and Windows.edb files and will produce 3 logs in the current working directory:
 ///
-/// DESKTOP-12345_File_Report_20230307_015244.json
+/// DESKTOP-12345_File_Report_20230307_015244_5e2a91c0.json
 ///
-/// DESKTOP-12345_Internet_History_Report_20230307_015317.json
+/// DESKTOP-12345_Internet_History_Report_20230307_015317_5e2a91c0.json
 ///
-/// DESKTOP-12345_Activity_History_Report_20230307_015317.json
+/// DESKTOP-12345_Activity_History_Report_20230307_015317_5e2a91c0.json
 ///
 /// Where the filename follows this format:
-/// HOSTNAME_ReportName_DateTime.json|csv.
+/// HOSTNAME_ReportName_DateTime_DbTag.json|csv.
 ///
 /// HOSTNAME is extracted from the database.
 
 #[derive(Parser)]
 #[command(author, version, about, long_about)]
 struct Cli {
-    /// Path to input directory (which will be recursively scanned for Windows.edb and Windows.db).
-    indir: PathBuf,
+    /// Paths to input directories (which will be recursively scanned for Windows.edb and Windows.db) or to single database files. Wildcards (*, ?, [..], **) are expanded.
+    #[arg(num_args = 1.., required = true)]
+    input: Vec<PathBuf>,
 
-    /// Output report format
-    #[arg(short, long, value_enum, default_value_t = ReportFormat::Json)]
-    format: ReportFormat,
+    /// Output report format: json, json-array, csv, tsv, xml, yaml, es-bulk, html, markdown, sqlite or xlsx.
+    /// Comma separated formats (e.g. json,csv) write a file per format; REPORT=FORMAT
+    /// (e.g. file=csv,internet=json) sets the format of one report type.
+    #[arg(short, long, value_parser = parse_format, value_delimiter = ',', default_value = "json")]
+    format: Vec<(Option<ReportKind>, ReportFormat)>,
 
     /// Output results to file or stdout
     #[arg(short, long, value_enum, default_value_t = ReportOutput::ToFile)]
@@ -118,101 +63,474 @@ struct Cli {
     /// Path to the directory where reports will be created (will be created if not present). Default is the current directory.
     #[arg(short, long, value_name = "OUTPUT DIRECTORY")]
     outdir: Option<PathBuf>,
+
+    /// Maximum depth of subdirectories to scan (0 - only the given directory). Default is unlimited.
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// Follow symbolic links while scanning input directories.
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Don't scan directories with this name, e.g. WinSxS (can be repeated, case insensitive).
+    #[arg(long, value_name = "NAME")]
+    ignore_dir: Vec<String>,
+
+    /// Scan files and directories whose name starts with a dot too, they are skipped by default.
+    #[arg(long)]
+    include_hidden: bool,
+
+    /// Skip databases larger than this many bytes found while scanning directories. Default is unlimited.
+    #[arg(long, value_name = "BYTES")]
+    max_db_size: Option<u64>,
+
+    /// Number of databases to process in parallel. Default is the number of CPU cores.
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Field delimiter used in csv reports (e.g. ';' for locales where ',' is the decimal separator).
+    #[arg(long, default_value_t = ',')]
+    delimiter: char,
+
+    /// Start csv and tsv report files with a UTF-8 byte order mark, so Excel shows non-ASCII paths correctly.
+    #[arg(long)]
+    csv_bom: bool,
+
+    /// Write the header row of csv and tsv reports that have no records, instead of leaving their file out.
+    #[arg(long)]
+    always_header: bool,
+
+    /// Keep line breaks in csv values inside the quoted value, as RFC 4180 allows. By default they are written as \r and \n, keeping every record on a single line.
+    #[arg(long)]
+    csv_multiline: bool,
+
+    /// Start csv and tsv report files with a '#' comment line of the tool version, host, source database and generation time (e.g. for pandas read_csv(comment='#')).
+    #[arg(long)]
+    csv_comments: bool,
+
+    /// Wrap json and json-array report files into an object with the tool version, host, source database and generation time, the records going into its "records" array.
+    #[arg(long)]
+    json_envelope: bool,
+
+    /// Index name put into the action lines of es-bulk reports. Default is to leave it to the _bulk request URL.
+    #[arg(long, value_name = "NAME")]
+    es_index: Option<String>,
+
+    /// Compress report files (reports written to stdout are not compressed).
+    #[arg(long, value_enum)]
+    compress: Option<ReportCompression>,
+
+    /// Timezone of the reported timestamps: UTC, local or an IANA name like America/New_York.
+    #[arg(long, default_value = "UTC")]
+    timezone: String,
+
+    /// Comma separated list of reports to generate. Default is all of them.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    reports: Vec<ReportKind>,
+
+    /// Only report records with a timestamp at or after this RFC 3339 date (e.g. 2023-03-07T00:00:00Z).
+    #[arg(long, value_parser = parse_datetime)]
+    after: Option<DateTime<Utc>>,
+
+    /// Only report records with a timestamp at or before this RFC 3339 date.
+    #[arg(long, value_parser = parse_datetime)]
+    before: Option<DateTime<Utc>>,
+
+    /// What --after/--before do with records that have no timestamp.
+    #[arg(long, value_enum, default_value_t = NoTimestampPolicy::Include)]
+    no_timestamp_policy: NoTimestampPolicy,
+
+    /// Only report records where some value contains this text (can be repeated, any of them matches).
+    #[arg(long, value_name = "STR")]
+    contains: Vec<String>,
+
+    /// Match --contains case sensitively.
+    #[arg(long)]
+    case_sensitive: bool,
+
+    /// Only report records where some value matches this regular expression (can be repeated). Combines with --contains: a record is reported when any of them matches.
+    #[arg(long, value_name = "PATTERN")]
+    regex: Vec<String>,
+
+    /// Comma separated list of fields to report, other fields are left out (csv headers too). Default is all fields.
+    #[arg(long, value_delimiter = ',', value_name = "FIELDS")]
+    fields: Vec<String>,
+
+    /// Leave this field out of the reports (can be repeated). Wins over --fields.
+    #[arg(long, value_name = "NAME")]
+    exclude_field: Vec<String>,
+
+    /// Replace the value of this field with [REDACTED] (can be repeated).
+    #[arg(long, value_name = "FIELD")]
+    redact: Vec<String>,
+
+    /// Replace redacted values with their SHA-256, so records stay correlatable.
+    #[arg(long, requires = "redact")]
+    redact_hash: bool,
+
+    /// Add a record_sha256 field with the SHA-256 of the record's fields and values.
+    #[arg(long)]
+    record_hash: bool,
+
+    /// Start every record with the _source_db (path of the database) and _host fields.
+    #[arg(long)]
+    include_source: bool,
+
+    /// Report binary values (Windows.db properties of other types than text and numbers) in this encoding.
+    #[arg(long, value_enum, default_value_t = BlobEncoding::Base64)]
+    blob_encoding: BlobEncoding,
+
+    /// Add a {FIELD}_Name field with the name of well-known SIDs (e.g. Local System for S-1-5-18) next to SID fields.
+    #[arg(long)]
+    resolve_sids: bool,
+
+    /// Write the records of every report sorted by this field, records without it last. The records of a report are held in memory until it is complete.
+    #[arg(long, value_name = "FIELD")]
+    sort_by: Option<String>,
+
+    /// Sort --sort-by in descending order.
+    #[arg(long, requires = "sort_by")]
+    sort_desc: bool,
+
+    /// Write at most this many records into every report of a database, and stop reading the database once all its reports have them.
+    #[arg(short = 'n', long)]
+    limit: Option<usize>,
+
+    /// Leave out records identical to one already written into the report. Takes memory for every distinct record of a report.
+    #[arg(long)]
+    dedupe: bool,
+
+    /// Don't write reports, print the number of records of every report type instead.
+    #[arg(long)]
+    count: bool,
+
+    /// Exit with an error code if no databases were found.
+    #[arg(long)]
+    fail_if_none: bool,
+
+    /// Stop at the first directory entry or database that can't be processed, instead of skipping it.
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// List the databases that would be processed, with their type and size, without parsing them.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Print the tables of the databases and their columns, without generating reports.
+    #[arg(long, conflicts_with = "dry_run")]
+    list_tables: bool,
+
+    /// Instead of the reports, write every column of every row of this table into a report named after it (can be repeated, wildcards like SystemIndex_* are expanded).
+    #[arg(long, value_name = "TABLE")]
+    raw: Vec<String>,
+
+    /// Log progress to stderr (-v), or more details (-vv, -vvv).
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Don't list the created reports and the records written (on stdout, or on stderr when the reports go to stdout).
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Pack the report files and the manifest into HOSTNAME_SIDR_DateTime.zip (or .tar.gz) in the output directory.
+    #[arg(long, value_enum, group = "archiving")]
+    archive: Option<ReportArchive>,
+
+    /// Same as --archive zip.
+    #[arg(long, group = "archiving")]
+    zip: bool,
+
+    /// Remove the files packed by --archive or --zip, leaving only the archive.
+    #[arg(long, alias = "zip-only", requires = "archiving")]
+    archive_only: bool,
+
+    /// Write a summary of the run (databases found and failed, records per report) to this JSON file.
+    #[arg(long, value_name = "FILE")]
+    summary_json: Option<PathBuf>,
+
+    /// Parse a temporary copy of databases that are locked by another process (e.g. the Windows Search service), taken from a volume shadow copy of their drive. Needs administrator rights.
+    #[arg(long)]
+    copy_locked: bool,
+
+    /// Ignore the write-ahead log (Windows.db-wal) of SQLite databases and report only what is in the database file.
+    #[arg(long)]
+    ignore_wal: bool,
+
+    /// Pick another name (with a _1, _2, ... suffix) for report files that already exist. By default SIDR stops.
+    #[arg(long, conflicts_with = "overwrite")]
+    no_clobber: bool,
+
+    /// Replace report files that already exist.
+    #[arg(long)]
+    overwrite: bool,
+
+    /// File name of the reports of a database, made of {host}, {report}, {date}, {time}, {dbtag}, {dirty} and {ext} (e.g. "CASE42_{host}_{report}_{date}.{ext}"). Default is HOSTNAME_ReportName_DateTime_DbTag.ext.
+    #[arg(long, value_name = "TEMPLATE")]
+    filename_template: Option<FilenameTemplate>,
+
+    /// Hostname used in the report file names instead of the one found in the databases.
+    #[arg(long)]
+    hostname: Option<String>,
+
+    /// Write the records of all databases into one report per type (All_Hosts_ReportName_DateTime.ext), implies --include-source.
+    #[arg(long)]
+    merge: bool,
+
+    /// Create the reports of every host in a subdirectory of the output directory named after the host.
+    #[arg(long, conflicts_with = "merge")]
+    per_host_dirs: bool,
+}
+
+// exit codes besides 0 (success) and 1 (the run could not be done at all)
+const EXIT_DB_FAILED: i32 = 2; // some databases could not be processed
+const EXIT_NO_DB: i32 = 3; // no databases were found and --fail-if-none was given
+const EXIT_INTERRUPTED: i32 = 130; // stopped by Ctrl-C, as shells report SIGINT
+
+fn parse_datetime(s: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|e| format!("expected an RFC 3339 date like 2023-03-07T00:00:00Z: {e}"))
+}
+
+// FORMAT, or REPORT=FORMAT for the format of one report type
+fn parse_format(s: &str) -> Result<(Option<ReportKind>, ReportFormat), String> {
+    fn parse<T: ValueEnum>(s: &str, what: &str) -> Result<T, String> {
+        T::from_str(s, true).map_err(|_| {
+            let values: Vec<String> = T::value_variants()
+                .iter()
+                .filter_map(|v| v.to_possible_value())
+                .map(|v| v.get_name().to_string())
+                .collect();
+            format!(
+                "unknown {what} '{s}' (possible values: {})",
+                values.join(", ")
+            )
+        })
+    }
+    match s.split_once('=') {
+        Some((kind, format)) => Ok((Some(parse(kind, "report")?), parse(format, "format")?)),
+        None => Ok((None, parse(s, "format")?)),
+    }
+}
+
+// Diagnostics always go to stderr, so they can't get mixed into reports written to stdout.
+// RUST_LOG overrides the level given by -v.
+fn init_logger(verbose: u8) {
+    let level = match verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .format_timestamp(None)
+        .init();
 }
 
 fn main() -> Result<(), SimpleError> {
     let cli = Cli::parse();
+    init_logger(cli.verbose);
 
     let output_dir = match cli.outdir {
         Some(outdir) => outdir,
         None => std::env::current_dir().map_err(|e| SimpleError::new(format!("{e}")))?,
     };
 
-    write_reports(&output_dir, cli.format, cli.report_type, &cli.indir)?;
-    Ok(())
-}
-
-fn write_reports(
-    rep_dir: &PathBuf,
-    format: ReportFormat,
-    report_type: ReportOutput,
-    input_dir: &PathBuf,
-) -> Result<(), SimpleError> {
-    let rep_producer = ReportProducer::new(rep_dir.as_path(), format, report_type);
-    let mut status_logger: Box<dyn std::io::Write> = match report_type {
-        ReportOutput::ToStdout => Box::new(std::io::sink()),
-        ReportOutput::ToFile => Box::new(std::io::stdout()),
+    if matches!(cli.delimiter, '"' | '\n' | '\r') {
+        return Err(SimpleError::new(format!(
+            "{:?} can't be used as a csv delimiter",
+            cli.delimiter
+        )));
+    }
+    let timezone: Timezone = cli.timezone.parse()?;
+    let regex = cli
+        .regex
+        .iter()
+        .map(|p| Regex::new(p).map_err(|e| SimpleError::new(format!("Invalid regex '{p}': {e}"))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let raw_tables = cli
+        .raw
+        .iter()
+        .map(|t| {
+            glob::Pattern::new(t)
+                .map_err(|e| SimpleError::new(format!("Invalid table pattern '{t}': {e}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for f in &cli.fields {
+        if !is_known_field(f) {
+            warn!("Unknown field '{f}' in --fields, it can only match Windows.db properties.");
+        }
+    }
+    let mut inputs = Vec::new();
+    for input in &cli.input {
+        inputs.extend(expand_input(input)?);
+    }
+    let scan_opts = ScanOptions {
+        max_depth: cli.max_depth,
+        follow_symlinks: cli.follow_symlinks,
+        fail_fast: cli.fail_fast,
+        quiet: cli.quiet,
+        copy_locked: cli.copy_locked,
+        ignore_wal: cli.ignore_wal,
+        ignore_dirs: cli.ignore_dir,
+        include_hidden: cli.include_hidden,
+        max_db_size: cli.max_db_size,
     };
-    dump(&input_dir, &rep_producer, &mut status_logger)?;
-    Ok(())
-}
-
-#[test]
-fn warn_dirty() {
-    use ese_parser_lib::ese_parser::EseParser;
-
-    let report_dir = PathBuf::from("tests/testdata");
-    let rep_producer = ReportProducer::new(
-        report_dir.as_path(),
-        ReportFormat::Csv,
-        ReportOutput::ToFile,
-    );
-    let ese_path = PathBuf::from("tests/testdata/Windows.edb");
-    assert!(ese_path.exists());
-    let jdb = Box::new(EseParser::load_from_path(10, ese_path).unwrap());
-    let edb_database_state = jdb.get_database_state();
-    assert!(rep_producer.is_db_dirty(Some(edb_database_state)));
-}
-
-#[test]
-fn test_generate_reports() {
-    use glob::glob;
-    use goldenfile::Mint;
-
-    let report_dir = PathBuf::from("tests/output");
-    let input_dir = PathBuf::from("tests/testdata");
-    let goldenfiles_dir = PathBuf::from("tests/goldenfiles");
-    let _ = write_reports(
-        &report_dir,
-        ReportFormat::Csv,
-        ReportOutput::ToFile,
-        &input_dir,
-    );
-    let _ = write_reports(
-        &report_dir,
-        ReportFormat::Json,
-        ReportOutput::ToFile,
-        &input_dir,
-    );
-
-    match fs::read_dir(goldenfiles_dir.clone()) {
-        Ok(ok_goldenfile_dir) => {
-            let mut mint_dir = Mint::new(".");
-            for goldenfile in ok_goldenfile_dir.flatten() {
-                let p = goldenfile.path();
-                let ext: &str = p.extension().unwrap().to_str().unwrap();
-                if let Some(f) = p.file_name() {
-                    if let Some(f) = f.to_str() {
-                        let parts: Vec<&str> = f.split('_').collect();
-                        let computerName = parts[0];
-                        let reportType = parts[1];
-                        for entry in glob(
-                            format!("tests/output/{computerName}_{reportType}*.{ext}").as_str(),
-                        )
-                        .unwrap()
-                        {
-                            let entry = entry.unwrap();
-                            let mut golden_file = mint_dir.new_goldenfile(&p).unwrap();
-                            let new_file = fs::read_to_string(&entry).unwrap();
-                            let _ = writeln!(&mut golden_file, "{}", new_file);
-                            let _ = fs::remove_file(entry);
+    if cli.dry_run {
+        // nothing is parsed and the output directory is not even created
+        let databases = find_databases(&inputs, &scan_opts)?;
+        for (p, kind) in &databases {
+            let size = fs::metadata(p).map(|m| m.len()).unwrap_or(0);
+            println!("{} ({kind:?}, {size} bytes)", p.to_string_lossy());
+        }
+        if cli.fail_if_none && databases.is_empty() {
+            eprintln!("No Windows Search databases found");
+            process::exit(EXIT_NO_DB);
+        }
+        return Ok(());
+    }
+    if cli.list_tables {
+        let databases = find_databases(&inputs, &scan_opts)?;
+        let mut failed = false;
+        for (p, kind) in &databases {
+            match list_tables(p, *kind) {
+                Ok(tables) => {
+                    println!("{} ({kind:?})", p.to_string_lossy());
+                    for (table, columns) in tables {
+                        println!("  {table}");
+                        for c in columns {
+                            println!("    {c}");
                         }
                     }
                 }
+                Err(e) => {
+                    eprintln!("Failed to open '{}': {e}", p.to_string_lossy());
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            process::exit(EXIT_DB_FAILED);
+        }
+        return Ok(());
+    }
+    let clobber = if cli.overwrite {
+        ClobberPolicy::Overwrite
+    } else if cli.no_clobber {
+        ClobberPolicy::Rename
+    } else {
+        ClobberPolicy::Fail
+    };
+    let mut formats: Vec<ReportFormat> = cli
+        .format
+        .iter()
+        .filter(|(kind, _)| kind.is_none())
+        .map(|(_, format)| *format)
+        .collect();
+    if formats.is_empty() {
+        formats.push(ReportFormat::Json);
+    }
+    let report_formats: Vec<(ReportKind, ReportFormat)> = cli
+        .format
+        .iter()
+        .filter_map(|(kind, format)| kind.map(|kind| (kind, *format)))
+        .collect();
+    if cli.report_type == ReportOutput::ToStdout
+        && cli.format.iter().any(|(_, format)| *format != formats[0])
+    {
+        return Err(SimpleError::new(
+            "Only one format can be written to stdout".to_string(),
+        ));
+    }
+    let rep_producer = ReportProducer::new(&output_dir, formats[0], cli.report_type)?
+        .with_formats(formats)
+        .with_report_formats(report_formats)
+        .with_csv_delimiter(cli.delimiter)
+        .with_csv_bom(cli.csv_bom)
+        .with_csv_always_header(cli.always_header)
+        .with_csv_multiline(cli.csv_multiline)
+        .with_csv_comments(cli.csv_comments)
+        .with_json_envelope(cli.json_envelope)
+        .with_es_index(cli.es_index)
+        .with_compress(cli.compress)
+        .with_timezone(timezone)
+        .with_reports(cli.reports)
+        .with_date_range(cli.after, cli.before, cli.no_timestamp_policy)
+        .with_contains(cli.contains, cli.case_sensitive)
+        .with_regex(regex)
+        .with_fields(cli.fields)
+        .with_exclude_fields(cli.exclude_field)
+        .with_redact(cli.redact, cli.redact_hash)
+        .with_record_hash(cli.record_hash)
+        .with_blob_encoding(cli.blob_encoding)
+        .with_resolve_sids(cli.resolve_sids)
+        .with_sort(cli.sort_by, cli.sort_desc)
+        .with_limit(cli.limit)
+        .with_dedupe(cli.dedupe)
+        .with_count_only(cli.count)
+        .with_include_source(cli.include_source)
+        .with_clobber(clobber)
+        .with_filename_template(cli.filename_template)
+        .with_hostname(cli.hostname)
+        .with_merge(cli.merge)
+        .with_per_host_dirs(cli.per_host_dirs)
+        .with_archive(
+            cli.archive.or(cli.zip.then_some(ReportArchive::Zip)),
+            cli.archive_only,
+        )
+        .with_raw_tables(raw_tables);
+
+    let threads = cli.threads.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+    // the first Ctrl-C lets the reports be finalized, so they stay parseable
+    ctrlc::set_handler(|| {
+        if is_interrupted() {
+            process::exit(EXIT_INTERRUPTED);
+        }
+        interrupt();
+        eprintln!("Interrupted, finishing the reports (press Ctrl-C again to quit at once)");
+    })
+    .map_err(|e| SimpleError::new(format!("Can't set the Ctrl-C handler: {e}")))?;
+    let summary = write_reports(&rep_producer, &inputs, &scan_opts, threads)?;
+    if let Some(path) = &cli.summary_json {
+        let json = serde_json::to_string_pretty(&summary).unwrap();
+        fs::write(path, json).map_err(|e| {
+            SimpleError::new(format!("Can't write \"{}\": {e}", path.to_string_lossy()))
+        })?;
+    }
+    match rep_producer.write_error() {
+        // stdout was closed (e.g. output piped into `head`), nobody reads the rest
+        Some(io::ErrorKind::BrokenPipe) => return Ok(()),
+        Some(_) => return Err(SimpleError::new("Failed to write the reports".to_string())),
+        None => {}
+    }
+    if cli.count {
+        let mut totals: Vec<(&str, usize)> = Vec::new();
+        for r in &summary.reports {
+            match totals.iter_mut().find(|(report, _)| *report == r.report) {
+                Some((_, records)) => *records += r.records,
+                None => totals.push((&r.report, r.records)),
             }
         }
-        Err(_) => {
-            panic!("Failed to read goldenfiles directory.")
+        for (report, records) in totals {
+            println!("{report}\t{records}");
         }
     }
+    // the reports are all written at this point
+    if is_interrupted() {
+        process::exit(EXIT_INTERRUPTED);
+    }
+    if summary.failed > 0 {
+        process::exit(EXIT_DB_FAILED);
+    }
+    if cli.fail_if_none && summary.found == 0 {
+        eprintln!("No Windows Search databases found");
+        process::exit(EXIT_NO_DB);
+    }
+    Ok(())
 }
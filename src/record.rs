@@ -0,0 +1,892 @@
+use base64::Engine;
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use simple_error::SimpleError;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use crate::ese::ESE_REPORT_COLUMNS;
+use crate::report::{Report, ReportKind};
+use crate::shared::well_known_sid_name;
+use crate::utils::*;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Timezone {
+    #[default]
+    Utc,
+    Local,
+    Named(Tz),
+}
+
+impl FromStr for Timezone {
+    type Err = SimpleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("utc") {
+            Ok(Timezone::Utc)
+        } else if s.eq_ignore_ascii_case("local") {
+            Ok(Timezone::Local)
+        } else {
+            s.parse::<Tz>().map(Timezone::Named).map_err(|_| {
+                SimpleError::new(format!(
+                    "Unknown timezone '{s}' (expected UTC, local or an IANA name like America/New_York)"
+                ))
+            })
+        }
+    }
+}
+
+impl Timezone {
+    pub fn format(&self, dt: DateTime<Utc>) -> String {
+        match self {
+            Timezone::Utc => format_date_time(dt),
+            Timezone::Local => format_date_time_offset(&dt.with_timezone(&Local)),
+            Timezone::Named(tz) => format_date_time_offset(&dt.with_timezone(tz)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum NoTimestampPolicy {
+    #[default]
+    Include,
+    Exclude,
+}
+
+// How binary values are written into the text of the reports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum BlobEncoding {
+    #[default]
+    Base64,
+    Hex,
+}
+
+impl BlobEncoding {
+    pub fn encode(&self, b: &[u8]) -> String {
+        match self {
+            BlobEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(b),
+            BlobEncoding::Hex => b.iter().map(|b| format!("{b:02x}")).collect(),
+        }
+    }
+}
+
+// Options applied to every record before it reaches the report format.
+#[derive(Clone, Debug, Default)]
+pub struct RecordOptions {
+    pub timezone: Timezone,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+    pub no_timestamp: NoTimestampPolicy, // what --after/--before do with records without one
+    pub contains: Vec<String>,           // lowercased unless case_sensitive
+    pub case_sensitive: bool,
+    pub regex: Vec<Regex>,
+    pub fields: Vec<String>,         // empty - all fields are reported
+    pub exclude_fields: Vec<String>, // wins over fields
+    pub redact: Vec<String>,
+    pub redact_hash: bool, // redacted values are replaced by their SHA-256 instead of REDACTED
+    pub record_hash: bool,
+    pub include_source: bool, // prepend the _source_db and _host fields to every record
+    pub blob_encoding: BlobEncoding,
+    pub resolve_sids: bool, // add a {field}_Name field with the name of well-known SIDs
+    pub sort_by: Option<String>, // records are held until the report is finished, then sorted
+    pub sort_desc: bool,
+    pub limit: Option<usize>, // records per report of a database
+    pub dedupe: bool,
+}
+
+const REDACTED: &str = "[REDACTED]";
+const RECORD_HASH_FIELD: &str = "record_sha256";
+
+// SHA-256 over the record's field=value lines sorted by field, so it doesn't depend on
+// the field order or the output format. Timestamps are hashed in UTC whatever --timezone is.
+fn record_sha256(values: &[(String, Value)]) -> String {
+    let mut pairs: Vec<(&str, String)> = values
+        .iter()
+        .map(|(f, v)| (f.as_str(), v.to_text()))
+        .collect();
+    pairs.sort();
+    let mut hasher = Sha256::new();
+    for (f, text) in pairs {
+        hasher.update(f.as_bytes());
+        hasher.update(b"=");
+        hasher.update(text.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// Windows.db properties of the reports and the fields RecordFilter adds to them,
+// used to warn about --fields that can never match.
+pub fn is_known_field(f: &str) -> bool {
+    let f = f.strip_suffix("_Name").unwrap_or(f);
+    f == "WorkId"
+        || f == "_source_db"
+        || f == "_host"
+        || f == RECORD_HASH_FIELD
+        || ESE_REPORT_COLUMNS.contains(&f)
+}
+
+impl RecordOptions {
+    fn is_in_range(&self, timestamp: Option<DateTime<Utc>>) -> bool {
+        if self.after.is_none() && self.before.is_none() {
+            return true;
+        }
+        match timestamp {
+            Some(ts) => {
+                self.after.map_or(true, |after| ts >= after)
+                    && self.before.map_or(true, |before| ts <= before)
+            }
+            None => self.no_timestamp == NoTimestampPolicy::Include,
+        }
+    }
+}
+
+impl RecordOptions {
+    fn is_field_selected(&self, f: &str) -> bool {
+        (self.fields.is_empty() || self.fields.iter().any(|i| i == f))
+            && !self.exclude_fields.iter().any(|i| i == f)
+    }
+
+    fn is_redacted(&self, f: &str) -> bool {
+        self.redact.iter().any(|i| i == f)
+    }
+
+    fn redact(&self, v: &Value) -> String {
+        if self.redact_hash {
+            format!("{:x}", Sha256::digest(v.to_text().as_bytes()))
+        } else {
+            REDACTED.to_string()
+        }
+    }
+
+    // --contains and --regex combine with OR: a record is kept when any of them matches any value
+    fn is_match(&self, values: &[(String, Value)]) -> bool {
+        if self.contains.is_empty() && self.regex.is_empty() {
+            return true;
+        }
+        values.iter().any(|(_, v)| {
+            let text = v.to_text();
+            if self.regex.iter().any(|re| re.is_match(&text)) {
+                return true;
+            }
+            let text = if self.case_sensitive {
+                text
+            } else {
+                text.to_lowercase()
+            };
+            self.contains.iter().any(|s| text.contains(s.as_str()))
+        })
+    }
+}
+
+// The timestamp a record is filtered by; records without it fall back to their first timestamp.
+fn primary_timestamp_field(report_suffix: &str) -> Option<&'static str> {
+    match report_suffix {
+        "File_Report" => Some("System_DateModified"),
+        "Internet_History_Report" => Some("System_Link_DateVisited"),
+        "Activity_History_Report" => Some("System_ActivityHistory_StartTime"),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Str(String),
+    Int(u64),
+    I64(i64),
+    Bool(bool),
+    Float(f64),
+    DateTime(DateTime<Utc>),
+    Blob(Vec<u8>), // only made by RecordCollector, the reports get binary values encoded
+}
+
+impl Value {
+    pub(crate) fn insert_into(self, r: &dyn Report, f: &str) {
+        match self {
+            Value::Str(s) => r.insert_str_val(f, s),
+            Value::Int(n) => r.insert_int_val(f, n),
+            Value::I64(n) => r.insert_i64_val(f, n),
+            Value::Bool(b) => r.insert_bool_val(f, b),
+            Value::Float(v) => r.insert_float_val(f, v),
+            Value::DateTime(dt) => r.insert_datetime_val(f, dt),
+            Value::Blob(b) => r.insert_blob_val(f, &b),
+        }
+    }
+
+    // numbers and dates by value, anything else by text
+    fn sort_cmp(&self, other: &Value) -> std::cmp::Ordering {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::I64(a), Value::I64(b)) => a.cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::DateTime(a), Value::DateTime(b)) => a.cmp(b),
+            (a, b) => a.to_text().cmp(&b.to_text()),
+        }
+    }
+
+    // the value as it is matched by --contains and --regex
+    fn to_text(&self) -> String {
+        match self {
+            Value::Str(s) => s.clone(),
+            Value::Int(n) => n.to_string(),
+            Value::I64(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Float(v) => v.to_string(),
+            Value::DateTime(dt) => format_date_time(*dt),
+            Value::Blob(b) => BlobEncoding::Base64.encode(b),
+        }
+    }
+}
+
+// A record of a report with its fields in report order, as ese_records and sqlite_records
+// read it. No RecordOptions are applied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub kind: ReportKind,
+    pub values: Vec<(String, Value)>,
+}
+
+impl Record {
+    pub fn get(&self, f: &str) -> Option<&Value> {
+        self.values
+            .iter()
+            .find(|(name, _)| name == f)
+            .map(|(_, v)| v)
+    }
+}
+
+// Takes the values of a single record from the functions that write records into reports,
+// so the record iterators share them. SIDs are kept as text, as in the reports.
+#[derive(Default)]
+pub(crate) struct RecordCollector {
+    values: RefCell<Vec<(String, Value)>>,
+}
+
+impl RecordCollector {
+    pub(crate) fn into_record(self, kind: ReportKind) -> Record {
+        Record {
+            kind,
+            values: self.values.into_inner(),
+        }
+    }
+
+    fn push(&self, f: &str, v: Value) {
+        self.values.borrow_mut().push((f.to_string(), v));
+    }
+}
+
+impl Report for RecordCollector {
+    fn create_new_row(&mut self) {}
+
+    fn insert_str_val(&self, f: &str, s: String) {
+        self.push(f, Value::Str(s));
+    }
+
+    fn insert_int_val(&self, f: &str, n: u64) {
+        self.push(f, Value::Int(n));
+    }
+
+    fn insert_i64_val(&self, f: &str, n: i64) {
+        self.push(f, Value::I64(n));
+    }
+
+    fn insert_bool_val(&self, f: &str, b: bool) {
+        self.push(f, Value::Bool(b));
+    }
+
+    fn insert_float_val(&self, f: &str, v: f64) {
+        self.push(f, Value::Float(v));
+    }
+
+    fn insert_datetime_val(&self, f: &str, dt: DateTime<Utc>) {
+        self.push(f, Value::DateTime(dt));
+    }
+
+    fn insert_blob_val(&self, f: &str, b: &[u8]) {
+        self.push(f, Value::Blob(b.to_vec()));
+    }
+
+    fn is_some_val_in_record(&self) -> bool {
+        !self.values.borrow().is_empty()
+    }
+}
+
+// Called with the report type and the fields of every record written, values as text.
+pub type RecordHook = Box<dyn FnMut(&str, &[(String, String)]) + Send>;
+
+// Collects the values of a record and hands them to the wrapped report once the record is
+// complete, so options that need to see the whole record can be applied format independently.
+pub struct RecordFilter {
+    inner: Box<dyn Report>,
+    report: String,
+    opts: Arc<RecordOptions>,
+    primary_timestamp: Option<&'static str>,
+    values: RefCell<Vec<(String, Value)>>,
+    records: Arc<AtomicUsize>,         // records written to inner
+    source: Vec<(String, String)>,     // fields added by --include-source
+    sorted: Vec<Vec<(String, Value)>>, // records held for --sort-by
+    written: usize,
+    seen: HashSet<String>, // record_sha256 of the records written, for --dedupe
+    hook: Option<Arc<Mutex<RecordHook>>>, // shared by the reports of all threads
+    // the first write error of the run, where the error of inner is kept once it is dropped
+    run_write_error: Option<Arc<Mutex<Option<io::ErrorKind>>>>,
+}
+
+impl RecordFilter {
+    pub fn new(
+        inner: Box<dyn Report>,
+        opts: Arc<RecordOptions>,
+        report_suffix: &str,
+        records: Arc<AtomicUsize>,
+    ) -> Self {
+        RecordFilter {
+            inner,
+            report: report_suffix.to_string(),
+            opts,
+            primary_timestamp: primary_timestamp_field(report_suffix),
+            values: RefCell::new(Vec::new()),
+            records,
+            source: Vec::new(),
+            sorted: Vec::new(),
+            written: 0,
+            seen: HashSet::new(),
+            hook: None,
+            run_write_error: None,
+        }
+    }
+
+    pub fn with_run_write_error(
+        mut self,
+        run_write_error: Arc<Mutex<Option<io::ErrorKind>>>,
+    ) -> Self {
+        self.run_write_error = Some(run_write_error);
+        self
+    }
+
+    pub fn with_hook(mut self, hook: Option<Arc<Mutex<RecordHook>>>) -> Self {
+        self.hook = hook;
+        self
+    }
+
+    pub fn with_source(mut self, dbpath: &Path, host: &str) -> Self {
+        if self.opts.include_source {
+            self.source = vec![
+                ("_source_db".into(), dbpath.to_string_lossy().into_owned()),
+                ("_host".into(), host.into()),
+            ];
+        }
+        self
+    }
+
+    fn timestamp(&self, values: &[(String, Value)]) -> Option<DateTime<Utc>> {
+        let mut first = None;
+        for (f, v) in values {
+            if let Value::DateTime(dt) = v {
+                if Some(f.as_str()) == self.primary_timestamp {
+                    return Some(*dt);
+                }
+                first = first.or(Some(*dt));
+            }
+        }
+        first
+    }
+
+    fn push(&self, f: &str, v: Value) {
+        if !self.opts.is_field_selected(f) {
+            return;
+        }
+        self.values.borrow_mut().push((f.to_string(), v));
+    }
+
+    fn write_values(&mut self) {
+        let mut values = std::mem::take(&mut *self.values.borrow_mut());
+        if self.is_full() {
+            return;
+        }
+        if !self.opts.is_in_range(self.timestamp(&values)) || !self.opts.is_match(&values) {
+            return;
+        }
+        if self.opts.dedupe && !self.seen.insert(record_sha256(&values)) {
+            return;
+        }
+        // filters above still see the original values, the hash covers the redacted ones
+        for (f, v) in values.iter_mut() {
+            if self.opts.is_redacted(f) {
+                *v = Value::Str(self.opts.redact(v));
+            }
+        }
+        if !self.source.is_empty() {
+            let source = self
+                .source
+                .iter()
+                .map(|(f, v)| (f.clone(), Value::Str(v.clone())));
+            values.splice(0..0, source);
+        }
+        if self.opts.record_hash {
+            let hash = record_sha256(&values);
+            values.push((RECORD_HASH_FIELD.to_string(), Value::Str(hash)));
+        }
+        if self.opts.sort_by.is_some() {
+            self.sorted.push(values);
+        } else {
+            self.replay(values);
+        }
+    }
+
+    // Records without the field go last either way; the sort is stable, so records with
+    // equal values stay in database order.
+    fn write_sorted(&mut self) {
+        let Some(sort_by) = &self.opts.sort_by else {
+            return;
+        };
+        let mut sorted = std::mem::take(&mut self.sorted);
+        let key =
+            |values: &[(String, Value)]| values.iter().find(|(f, _)| f == sort_by).map(|(_, v)| v);
+        sorted.sort_by(|a, b| match (key(a), key(b)) {
+            (Some(a), Some(b)) if self.opts.sort_desc => b.sort_cmp(a),
+            (Some(a), Some(b)) => a.sort_cmp(b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        });
+        // the limit goes for the sorted records, so all of them had to be read
+        sorted.truncate(self.opts.limit.unwrap_or(usize::MAX));
+        for values in sorted {
+            self.replay(values);
+        }
+    }
+
+    fn replay(&mut self, values: Vec<(String, Value)>) {
+        if let Some(hook) = &self.hook {
+            let fields: Vec<(String, String)> = values
+                .iter()
+                .map(|(f, v)| match (v, &self.opts.timezone) {
+                    (Value::DateTime(dt), tz) => (f.clone(), tz.format(*dt)),
+                    (v, _) => (f.clone(), v.to_text()),
+                })
+                .collect();
+            (hook.lock().unwrap())(&self.report, &fields);
+        }
+        for (f, v) in values {
+            match (v, &self.opts.timezone) {
+                (Value::DateTime(dt), tz) if *tz != Timezone::Utc => {
+                    self.inner.insert_str_val(&f, tz.format(dt))
+                }
+                (v, _) => v.insert_into(&*self.inner, &f),
+            }
+        }
+        self.inner.create_new_row();
+        self.records.fetch_add(1, Ordering::Relaxed);
+        self.written += 1;
+    }
+}
+
+impl Report for RecordFilter {
+    fn footer(&mut self) {
+        self.create_new_row();
+        self.write_sorted();
+        self.inner.footer();
+    }
+
+    fn create_new_row(&mut self) {
+        if self.is_some_val_in_record() {
+            self.write_values();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.inner.flush();
+    }
+
+    fn insert_str_val(&self, f: &str, s: String) {
+        self.push(f, Value::Str(s));
+    }
+
+    fn insert_int_val(&self, f: &str, n: u64) {
+        self.push(f, Value::Int(n));
+    }
+
+    fn insert_i64_val(&self, f: &str, n: i64) {
+        self.push(f, Value::I64(n));
+    }
+
+    fn insert_bool_val(&self, f: &str, b: bool) {
+        self.push(f, Value::Bool(b));
+    }
+
+    fn insert_float_val(&self, f: &str, v: f64) {
+        self.push(f, Value::Float(v));
+    }
+
+    fn insert_datetime_val(&self, f: &str, dt: DateTime<Utc>) {
+        self.push(f, Value::DateTime(dt));
+    }
+
+    fn insert_blob_val(&self, f: &str, b: &[u8]) {
+        self.push(f, Value::Str(self.opts.blob_encoding.encode(b)));
+    }
+
+    fn insert_sid_val(&self, f: &str, b: &[u8]) {
+        let sid = sid_to_string(b);
+        let name = well_known_sid_name(&sid).filter(|_| self.opts.resolve_sids);
+        self.push(f, Value::Str(sid));
+        if let Some(name) = name {
+            self.push(&format!("{f}_Name"), Value::Str(name.to_string()));
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        // nothing more can be written after an error
+        if self.inner.write_error().is_some() {
+            return true;
+        }
+        match self.opts.limit {
+            Some(limit) if self.opts.sort_by.is_none() => self.written >= limit,
+            _ => false,
+        }
+    }
+
+    fn write_error(&self) -> Option<io::ErrorKind> {
+        self.inner.write_error()
+    }
+
+    fn set_field(&self, f: &str) {
+        if self.opts.is_field_selected(f) {
+            self.inner.set_field(f);
+        }
+    }
+
+    fn is_some_val_in_record(&self) -> bool {
+        !self.values.borrow().is_empty()
+    }
+}
+
+impl Drop for RecordFilter {
+    fn drop(&mut self) {
+        self.footer();
+        if let (Some(run_write_error), Some(kind)) = (&self.run_write_error, self.write_error()) {
+            run_write_error.lock().unwrap().get_or_insert(kind);
+        }
+    }
+}
+
+// A record on its way to a report shared by all databases (--merge): the report file and type.
+pub(crate) type MergedRecord = (PathBuf, String, Vec<(String, Value)>);
+
+// Stands in for a merged report in a database's thread, handing its records whole to the
+// thread that writes the merged reports, so records of different databases don't mix.
+pub(crate) struct MergeSender {
+    tx: Sender<MergedRecord>,
+    path: PathBuf,
+    report_suffix: String,
+    values: RefCell<Vec<(String, Value)>>,
+}
+
+impl MergeSender {
+    pub(crate) fn new(tx: Sender<MergedRecord>, path: PathBuf, report_suffix: &str) -> Self {
+        MergeSender {
+            tx,
+            path,
+            report_suffix: report_suffix.to_string(),
+            values: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn push(&self, f: &str, v: Value) {
+        self.values.borrow_mut().push((f.to_string(), v));
+    }
+}
+
+impl Report for MergeSender {
+    fn create_new_row(&mut self) {
+        if self.is_some_val_in_record() {
+            let values = std::mem::take(&mut *self.values.borrow_mut());
+            // the writer only stops once every sender is gone
+            let _ = self
+                .tx
+                .send((self.path.clone(), self.report_suffix.clone(), values));
+        }
+    }
+
+    fn footer(&mut self) {
+        self.create_new_row();
+    }
+
+    fn insert_str_val(&self, f: &str, s: String) {
+        self.push(f, Value::Str(s));
+    }
+
+    fn insert_int_val(&self, f: &str, n: u64) {
+        self.push(f, Value::Int(n));
+    }
+
+    fn insert_i64_val(&self, f: &str, n: i64) {
+        self.push(f, Value::I64(n));
+    }
+
+    fn insert_bool_val(&self, f: &str, b: bool) {
+        self.push(f, Value::Bool(b));
+    }
+
+    fn insert_float_val(&self, f: &str, v: f64) {
+        self.push(f, Value::Float(v));
+    }
+
+    fn insert_datetime_val(&self, f: &str, dt: DateTime<Utc>) {
+        self.push(f, Value::DateTime(dt));
+    }
+
+    fn is_some_val_in_record(&self) -> bool {
+        !self.values.borrow().is_empty()
+    }
+}
+
+impl Drop for MergeSender {
+    fn drop(&mut self) {
+        self.footer();
+    }
+}
+
+#[test]
+fn test_is_in_range() {
+    let ts = |d| Utc.with_ymd_and_hms(2023, 3, d, 0, 0, 0).unwrap();
+    let mut opts = RecordOptions::default();
+    assert!(opts.is_in_range(None));
+    opts.after = Some(ts(2));
+    opts.before = Some(ts(4));
+    assert!(!opts.is_in_range(Some(ts(1))));
+    assert!(opts.is_in_range(Some(ts(2))));
+    assert!(opts.is_in_range(Some(ts(4))));
+    assert!(!opts.is_in_range(Some(ts(5))));
+    assert!(opts.is_in_range(None));
+    opts.no_timestamp = NoTimestampPolicy::Exclude;
+    assert!(!opts.is_in_range(None));
+}
+
+#[test]
+fn test_is_match() {
+    let values = vec![
+        (
+            "path".to_string(),
+            Value::Str("C:\\Users\\Dropbox\\a.txt".into()),
+        ),
+        ("size".to_string(), Value::Int(1024)),
+    ];
+    let mut opts = RecordOptions::default();
+    assert!(opts.is_match(&values));
+    opts.contains = vec!["dropbox".into()];
+    assert!(opts.is_match(&values));
+    opts.case_sensitive = true;
+    assert!(!opts.is_match(&values));
+    opts.contains = vec!["OneDrive".into(), "1024".into()];
+    assert!(opts.is_match(&values));
+    opts.contains = vec!["OneDrive".into()];
+    opts.regex = vec![Regex::new(r"\.(exe|dll|ps1)$").unwrap()];
+    assert!(!opts.is_match(&values));
+    opts.regex.push(Regex::new(r"(?i)\\dropbox\\").unwrap());
+    assert!(opts.is_match(&values));
+}
+
+#[test]
+fn test_is_known_field() {
+    assert!(is_known_field("WorkId"));
+    assert!(is_known_field("System_ItemPathDisplay"));
+    assert!(is_known_field("_source_db"));
+    assert!(is_known_field("_host"));
+    assert!(is_known_field("record_sha256"));
+    assert!(is_known_field("System_FileOwner_Name"));
+    assert!(!is_known_field("System_NoSuchProperty"));
+    assert!(!is_known_field("_Name"));
+}
+
+#[test]
+fn test_is_field_selected() {
+    let mut opts = RecordOptions::default();
+    assert!(opts.is_field_selected("System_Size"));
+    opts.fields = vec!["System_ItemPathDisplay".into(), "System_Size".into()];
+    assert!(opts.is_field_selected("System_Size"));
+    assert!(!opts.is_field_selected("System_FileOwner"));
+    opts.exclude_fields = vec!["System_Size".into()];
+    assert!(!opts.is_field_selected("System_Size"));
+    assert!(opts.is_field_selected("System_ItemPathDisplay"));
+    opts.fields.clear();
+    assert!(!opts.is_field_selected("System_Size"));
+    assert!(opts.is_field_selected("System_FileOwner"));
+}
+
+#[test]
+fn test_redact() {
+    let mut opts = RecordOptions {
+        redact: vec!["System_FileOwner".into()],
+        ..Default::default()
+    };
+    assert!(opts.is_redacted("System_FileOwner"));
+    assert!(!opts.is_redacted("System_Size"));
+    let owner = Value::Str("DESKTOP-12345\\user".into());
+    assert_eq!(opts.redact(&owner), REDACTED);
+    opts.redact_hash = true;
+    assert_eq!(
+        opts.redact(&Value::Str("abc".into())),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(opts.redact(&owner), opts.redact(&owner));
+}
+
+#[test]
+fn test_record_sha256() {
+    let path = || ("path".to_string(), Value::Str("C:\\a.txt".into()));
+    let size = || ("size".to_string(), Value::Int(1024));
+    assert_eq!(
+        record_sha256(&[path(), size()]),
+        record_sha256(&[size(), path()])
+    );
+    assert_ne!(
+        record_sha256(&[path(), size()]),
+        record_sha256(&[path(), ("size".to_string(), Value::Int(1025))])
+    );
+}
+
+// Writes records through a RecordFilter with opts into a csv report and returns the report.
+#[cfg(test)]
+fn filtered_csv(opts: RecordOptions, write: impl FnOnce(&mut RecordFilter)) -> String {
+    use crate::report::{ReportCsv, ReportOutput};
+    use tempdir::TempDir;
+
+    let dir = TempDir::new("sidr_filtered").unwrap();
+    let p = dir.path().join("filtered.csv");
+    let csv = ReportCsv::new(&p, ReportOutput::ToFile, None, ',', false).unwrap();
+    {
+        let mut r = RecordFilter::new(Box::new(csv), Arc::new(opts), "File_Report", Arc::default())
+            .with_source(Path::new("C:\\Windows.edb"), "DESKTOP-12345");
+        write(&mut r);
+    }
+    std::fs::read_to_string(&p).unwrap()
+}
+
+#[test]
+fn test_include_source() {
+    let opts = RecordOptions {
+        include_source: true,
+        ..Default::default()
+    };
+    let data = filtered_csv(opts, |r| {
+        r.insert_int_val("WorkId", 1);
+        r.create_new_row();
+    });
+    assert_eq!(
+        data,
+        "_source_db,_host,WorkId\n\"C:\\Windows.edb\",\"DESKTOP-12345\",1"
+    );
+}
+
+#[test]
+fn test_blob_encoding() {
+    let b = [0x00, 0x7f, 0xff, b'S', b'I', b'D', b'R'];
+    assert_eq!(BlobEncoding::Base64.encode(&b), "AH//U0lEUg==");
+    assert_eq!(BlobEncoding::Hex.encode(&b), "007fff53494452");
+    assert_eq!(BlobEncoding::Hex.encode(&[]), "");
+
+    // base64 unless --blob-encoding says otherwise
+    let data = filtered_csv(RecordOptions::default(), |r| {
+        r.insert_int_val("WorkId", 1);
+        r.insert_blob_val("System_Kind", &b);
+    });
+    assert_eq!(data, "WorkId,System_Kind\n1,\"AH//U0lEUg==\"");
+}
+
+#[test]
+fn test_resolve_sids() {
+    let local_system = [1, 1, 0, 0, 0, 0, 0, 5, 18, 0, 0, 0];
+    let opts = RecordOptions {
+        resolve_sids: true,
+        ..Default::default()
+    };
+    let data = filtered_csv(opts, |r| {
+        r.insert_sid_val("OwnerSid", &local_system);
+        r.create_new_row();
+    });
+    assert_eq!(
+        data,
+        "OwnerSid,OwnerSid_Name\n\"S-1-5-18\",\"Local System\""
+    );
+}
+
+#[test]
+fn test_sort_by() {
+    for (sort_desc, expected) in [
+        (false, "WorkId,System_Size\n3,2\n1,10\n2,10\n4,"),
+        (true, "WorkId,System_Size\n1,10\n2,10\n3,2\n4,"),
+    ] {
+        let opts = RecordOptions {
+            sort_by: Some("System_Size".into()),
+            sort_desc,
+            ..Default::default()
+        };
+        let data = filtered_csv(opts, |r| {
+            for (work_id, size) in [(1, Some(10)), (2, Some(10)), (3, Some(2)), (4, None)] {
+                r.insert_int_val("WorkId", work_id);
+                if let Some(size) = size {
+                    r.insert_int_val("System_Size", size);
+                }
+                r.create_new_row();
+            }
+        });
+        assert_eq!(data, expected);
+    }
+}
+
+#[test]
+fn test_limit() {
+    let opts = RecordOptions {
+        limit: Some(2),
+        ..Default::default()
+    };
+    let data = filtered_csv(opts, |r| {
+        for work_id in 1..=3 {
+            r.insert_int_val("WorkId", work_id);
+            r.create_new_row();
+            assert_eq!(r.is_full(), work_id >= 2);
+        }
+    });
+    assert_eq!(data, "WorkId\n1\n2");
+}
+
+#[test]
+fn test_dedupe() {
+    let opts = RecordOptions {
+        dedupe: true,
+        ..Default::default()
+    };
+    let data = filtered_csv(opts, |r| {
+        for (work_id, url) in [(1, "a"), (1, "a"), (1, "b"), (1, "a")] {
+            r.insert_int_val("WorkId", work_id);
+            r.insert_str_val("System_ItemUrl", url.into());
+            r.create_new_row();
+        }
+        assert_eq!(r.records.load(Ordering::Relaxed), 2);
+    });
+    assert_eq!(data, "WorkId,System_ItemUrl\n1,\"a\"\n1,\"b\"");
+}
+
+#[test]
+fn test_timezone() {
+    let dt = Utc.with_ymd_and_hms(2023, 3, 7, 1, 52, 44).unwrap();
+    assert_eq!(
+        "UTC".parse::<Timezone>().unwrap().format(dt),
+        "2023-03-07T01:52:44.0000000Z"
+    );
+    assert_eq!(
+        "America/New_York".parse::<Timezone>().unwrap().format(dt),
+        "2023-03-06T20:52:44.0000000-05:00"
+    );
+    assert_eq!("local".parse::<Timezone>().unwrap(), Timezone::Local);
+    assert!("Mars/Olympus_Mons".parse::<Timezone>().is_err());
+}
@@ -1,21 +1,44 @@
 use chrono::prelude::*;
 use clap::ValueEnum;
 use ese_parser_lib::parser::jet::DbState;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{error, info};
+use regex::Regex;
+use rust_xlsxwriter::{Workbook, XlsxError};
+use serde::Serialize;
 use serde_json;
+use sha2::{Digest, Sha256};
 use simple_error::SimpleError;
 use std::cell::{Cell, RefCell};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::fs::File;
 use std::io::{self, BufWriter, Write};
-use std::ops::IndexMut;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 
+use crate::record::{
+    BlobEncoding, MergeSender, MergedRecord, NoTimestampPolicy, RecordFilter, RecordHook,
+    RecordOptions, Timezone,
+};
 use crate::utils::*;
 
-#[derive(Clone, Debug, ValueEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
 pub enum ReportFormat {
     Json,
+    JsonArray,
     Csv,
+    Tsv,
+    Xml,
+    Yaml,
+    EsBulk,
+    Html,
+    Markdown,
+    Sqlite,
+    Xlsx,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
@@ -24,6 +47,101 @@ pub enum ReportOutput {
     ToStdout,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ReportCompression {
+    Gzip,
+}
+
+// Archive the report files are packed into at the end of the run.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ReportArchive {
+    Zip,
+    #[value(name = "tar.gz")]
+    TarGz,
+}
+
+// What to do when a report file already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ClobberPolicy {
+    #[default]
+    Fail,
+    Rename, // add a _1, _2, ... suffix to the file name
+    Overwrite,
+}
+
+// File name of the reports of a database, with {placeholder}s filled in per report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilenameTemplate(String);
+
+const FILENAME_PLACEHOLDERS: &[&str] = &["host", "report", "date", "time", "dbtag", "dirty", "ext"];
+
+impl FilenameTemplate {
+    // None for a '{' without its '}'
+    fn placeholders(&self) -> impl Iterator<Item = Option<&str>> {
+        self.0
+            .split('{')
+            .skip(1)
+            .map(|s| s.split_once('}').map(|(name, _)| name))
+    }
+
+    // values are looked up by placeholder name, unknown ones are rejected by from_str
+    fn render(&self, values: &[(&str, &str)]) -> String {
+        let mut name = self.0.clone();
+        for (placeholder, value) in values {
+            name = name.replace(&format!("{{{placeholder}}}"), value);
+        }
+        name
+    }
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = SimpleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let template = FilenameTemplate(s.to_string());
+        for p in template.placeholders() {
+            match p {
+                None => {
+                    return Err(SimpleError::new(format!(
+                        "Unterminated placeholder in filename template '{s}'"
+                    )))
+                }
+                Some(p) if !FILENAME_PLACEHOLDERS.contains(&p) => {
+                    return Err(SimpleError::new(format!(
+                        "Unknown placeholder '{{{p}}}' in filename template (expected one of {})",
+                        FILENAME_PLACEHOLDERS
+                            .iter()
+                            .map(|p| format!("{{{p}}}"))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )))
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(template)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ReportKind {
+    File,
+    Internet,
+    Activity,
+}
+
+impl ReportKind {
+    // None for the tables dumped by --raw
+    pub fn from_suffix(report_suffix: &str) -> Option<ReportKind> {
+        match report_suffix {
+            "File_Report" => Some(ReportKind::File),
+            "Internet_History_Report" => Some(ReportKind::Internet),
+            "Activity_History_Report" => Some(ReportKind::Activity),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ReportSuffix {
     FileReport,
@@ -45,14 +163,18 @@ impl ReportSuffix {
     // Autogenerating the names from the enum values by deriving Debug is another option.
     // However, if someone decided to change the name of one of these enums,
     // it could break downstream processing.
-    pub fn message(&self) -> String {
+    pub fn name(&self) -> &'static str {
         match self {
-            Self::FileReport => serde_json::to_string("file_report").unwrap(),
-            Self::ActivityHistory => serde_json::to_string("activity_history").unwrap(),
-            Self::InternetHistory => serde_json::to_string("internet_history").unwrap(),
-            Self::Unknown => serde_json::to_string("").unwrap(),
+            Self::FileReport => "file_report",
+            Self::ActivityHistory => "activity_history",
+            Self::InternetHistory => "internet_history",
+            Self::Unknown => "",
         }
     }
+
+    pub fn message(&self) -> String {
+        serde_json::to_string(self.name()).unwrap()
+    }
 }
 
 impl Display for ReportSuffix {
@@ -63,21 +185,361 @@ impl Display for ReportSuffix {
 
 pub struct ReportProducer {
     dir: PathBuf,
-    format: ReportFormat,
+    formats: Vec<ReportFormat>, // a file per format is written for every report
+    report_formats: Vec<(ReportKind, ReportFormat)>, // replace formats for these report types
     report_type: ReportOutput,
+    csv_delimiter: char,
+    csv_bom: bool,
+    csv_always_header: bool, // csv reports without records get their header
+    csv_multiline: bool,     // CR/LF in csv values are kept instead of written as \r/\n
+    es_index: Option<String>,
+    compress: Option<ReportCompression>,
+    started: DateTime<Utc>, // names the single file of the sqlite and xlsx formats
+    xlsx: Arc<Mutex<XlsxBook>>,
+    sqlite: Mutex<Option<Arc<Mutex<SqliteDb>>>>, // opened by the first sqlite report
+    record_opts: Arc<RecordOptions>,
+    reports: Vec<ReportKind>,     // empty - all
+    created: Mutex<Vec<PathBuf>>, // report files of the run, listed in the manifest
+    // database and records written of every report made
+    counters: Mutex<Vec<(PathBuf, ReportSummary, Arc<AtomicUsize>)>>,
+    clobber: ClobberPolicy,
+    // the sqlite and xlsx files, chosen by the first report of the format
+    combined_paths: Mutex<Vec<(ReportFormat, PathBuf)>>,
+    resolved_paths: Mutex<Vec<PathBuf>>, // report file names handed out, see resolve_path
+    filename_template: Option<FilenameTemplate>, // None - get_path_db_status layout
+    hostname: Option<String>,            // replaces the hostname recovered from the databases
+    merge: bool,                         // one report per type for all databases
+    merge_tx: Mutex<Option<Sender<MergedRecord>>>,
+    merge_rx: Mutex<Option<Receiver<MergedRecord>>>,
+    // merged report file of every report type and format
+    merged_paths: Mutex<Vec<(String, PathBuf, ReportFormat)>>,
+    per_host_dirs: bool, // reports go to a subdirectory named after the host
+    raw_tables: Vec<glob::Pattern>, // --raw: the tables dumped instead of the reports
+    count_only: bool,    // --count: records are counted, nothing is written
+    // pack the report files and the manifest into an archive at the end
+    archive: Option<ReportArchive>,
+    archive_only: bool,  // remove the files packed into the archive
+    json_envelope: bool, // json report files are wrapped into an object with the run metadata
+    csv_comments: bool,  // csv report files start with a comment line of the run metadata
+    record_hook: Option<Arc<Mutex<RecordHook>>>,
+    write_error: Arc<Mutex<Option<io::ErrorKind>>>, // the first error writing a report
 }
 
+const MERGED_HOSTNAME: &str = "All_Hosts";
+
+// Records written to a report file (or to stdout, with no path) for a host.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ReportSummary {
+    pub host: String,
+    pub report: String,
+    pub path: Option<PathBuf>,
+    pub records: usize,
+    pub partial: bool, // the database could only be read up to a corrupted part
+}
+
+const MANIFEST_FILE: &str = "manifest.json";
+// used when a database doesn't name its host
+pub const UNKNOWN_HOSTNAME: &str = "Unknown";
+
 impl ReportProducer {
-    pub fn new(dir: &Path, format: ReportFormat, report_type: ReportOutput) -> Self {
-        if !dir.exists() {
-            std::fs::create_dir(dir)
-                .unwrap_or_else(|_| panic!("Can't create directory \"{}\"", dir.to_string_lossy()));
+    pub fn new(
+        dir: &Path,
+        format: ReportFormat,
+        report_type: ReportOutput,
+    ) -> Result<Self, SimpleError> {
+        // -o report.json would otherwise only fail once the first report is created in it
+        if dir.exists() && !dir.is_dir() {
+            return Err(SimpleError::new(format!(
+                "Output directory \"{}\" exists and is not a directory",
+                dir.to_string_lossy()
+            )));
         }
-        ReportProducer {
+        std::fs::create_dir_all(dir).map_err(|e| {
+            SimpleError::new(format!(
+                "Can't create directory \"{}\": {e}",
+                dir.to_string_lossy()
+            ))
+        })?;
+        Ok(ReportProducer {
             dir: dir.to_path_buf(),
-            format,
+            formats: vec![format],
+            report_formats: Vec::new(),
             report_type,
+            csv_delimiter: ',',
+            csv_bom: false,
+            csv_always_header: false,
+            csv_multiline: false,
+            es_index: None,
+            compress: None,
+            started: Utc::now(),
+            xlsx: Arc::new(Mutex::new(XlsxBook::new())),
+            sqlite: Mutex::new(None),
+            record_opts: Arc::new(RecordOptions::default()),
+            reports: Vec::new(),
+            created: Mutex::new(Vec::new()),
+            counters: Mutex::new(Vec::new()),
+            clobber: ClobberPolicy::default(),
+            combined_paths: Mutex::new(Vec::new()),
+            resolved_paths: Mutex::new(Vec::new()),
+            filename_template: None,
+            hostname: None,
+            merge: false,
+            merge_tx: Mutex::new(None),
+            merge_rx: Mutex::new(None),
+            merged_paths: Mutex::new(Vec::new()),
+            per_host_dirs: false,
+            raw_tables: Vec::new(),
+            count_only: false,
+            archive: None,
+            archive_only: false,
+            json_envelope: false,
+            csv_comments: false,
+            record_hook: None,
+            write_error: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    // Every report is written in all of these formats, into a file each. The first one
+    // replaces the format given to new(); duplicates are dropped.
+    pub fn with_formats(mut self, formats: Vec<ReportFormat>) -> Self {
+        if !formats.is_empty() {
+            self.formats.clear();
+            for format in formats {
+                if !self.formats.contains(&format) {
+                    self.formats.push(format);
+                }
+            }
+        }
+        self
+    }
+
+    // The formats of a report type, instead of the ones of with_formats(); a report type can
+    // be given several times to be written in several formats.
+    pub fn with_report_formats(mut self, report_formats: Vec<(ReportKind, ReportFormat)>) -> Self {
+        self.report_formats = report_formats;
+        self
+    }
+
+    fn formats_of(&self, report_suffix: &str) -> Vec<ReportFormat> {
+        let kind = ReportKind::from_suffix(report_suffix);
+        let mut formats = Vec::new();
+        for (k, format) in &self.report_formats {
+            if Some(*k) == kind && !formats.contains(format) {
+                formats.push(*format);
+            }
+        }
+        if formats.is_empty() {
+            formats = self.formats.clone();
+        }
+        formats
+    }
+
+    pub fn with_csv_delimiter(mut self, delimiter: char) -> Self {
+        self.csv_delimiter = delimiter;
+        self
+    }
+
+    pub fn with_csv_bom(mut self, csv_bom: bool) -> Self {
+        self.csv_bom = csv_bom;
+        self
+    }
+
+    pub fn with_csv_always_header(mut self, csv_always_header: bool) -> Self {
+        self.csv_always_header = csv_always_header;
+        self
+    }
+
+    pub fn with_csv_multiline(mut self, csv_multiline: bool) -> Self {
+        self.csv_multiline = csv_multiline;
+        self
+    }
+
+    pub fn with_es_index(mut self, es_index: Option<String>) -> Self {
+        self.es_index = es_index;
+        self
+    }
+
+    pub fn with_compress(mut self, compress: Option<ReportCompression>) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    pub fn with_timezone(mut self, timezone: Timezone) -> Self {
+        Arc::make_mut(&mut self.record_opts).timezone = timezone;
+        self
+    }
+
+    pub fn with_date_range(
+        mut self,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+        no_timestamp: NoTimestampPolicy,
+    ) -> Self {
+        let opts = Arc::make_mut(&mut self.record_opts);
+        opts.after = after;
+        opts.before = before;
+        opts.no_timestamp = no_timestamp;
+        self
+    }
+
+    pub fn with_contains(mut self, contains: Vec<String>, case_sensitive: bool) -> Self {
+        let opts = Arc::make_mut(&mut self.record_opts);
+        opts.contains = if case_sensitive {
+            contains
+        } else {
+            contains.iter().map(|s| s.to_lowercase()).collect()
+        };
+        opts.case_sensitive = case_sensitive;
+        self
+    }
+
+    pub fn with_regex(mut self, regex: Vec<Regex>) -> Self {
+        Arc::make_mut(&mut self.record_opts).regex = regex;
+        self
+    }
+
+    pub fn with_fields(mut self, fields: Vec<String>) -> Self {
+        Arc::make_mut(&mut self.record_opts).fields = fields;
+        self
+    }
+
+    pub fn with_exclude_fields(mut self, exclude_fields: Vec<String>) -> Self {
+        Arc::make_mut(&mut self.record_opts).exclude_fields = exclude_fields;
+        self
+    }
+
+    pub fn with_redact(mut self, redact: Vec<String>, redact_hash: bool) -> Self {
+        let opts = Arc::make_mut(&mut self.record_opts);
+        opts.redact = redact;
+        opts.redact_hash = redact_hash;
+        self
+    }
+
+    pub fn with_include_source(mut self, include_source: bool) -> Self {
+        Arc::make_mut(&mut self.record_opts).include_source = include_source;
+        self
+    }
+
+    pub fn with_blob_encoding(mut self, blob_encoding: BlobEncoding) -> Self {
+        Arc::make_mut(&mut self.record_opts).blob_encoding = blob_encoding;
+        self
+    }
+
+    pub fn with_resolve_sids(mut self, resolve_sids: bool) -> Self {
+        Arc::make_mut(&mut self.record_opts).resolve_sids = resolve_sids;
+        self
+    }
+
+    pub fn with_sort(mut self, sort_by: Option<String>, sort_desc: bool) -> Self {
+        let record_opts = Arc::make_mut(&mut self.record_opts);
+        record_opts.sort_by = sort_by;
+        record_opts.sort_desc = sort_desc;
+        self
+    }
+
+    pub fn with_dedupe(mut self, dedupe: bool) -> Self {
+        Arc::make_mut(&mut self.record_opts).dedupe = dedupe;
+        self
+    }
+
+    pub fn with_limit(mut self, limit: Option<usize>) -> Self {
+        Arc::make_mut(&mut self.record_opts).limit = limit;
+        self
+    }
+
+    pub fn with_record_hash(mut self, record_hash: bool) -> Self {
+        Arc::make_mut(&mut self.record_opts).record_hash = record_hash;
+        self
+    }
+
+    pub fn with_clobber(mut self, clobber: ClobberPolicy) -> Self {
+        self.clobber = clobber;
+        self
+    }
+
+    pub fn with_filename_template(mut self, filename_template: Option<FilenameTemplate>) -> Self {
+        self.filename_template = filename_template;
+        self
+    }
+
+    pub fn with_hostname(mut self, hostname: Option<String>) -> Self {
+        self.hostname = hostname;
+        self
+    }
+
+    // Merged records keep their host in the _host field, so --include-source comes with it.
+    pub fn with_merge(mut self, merge: bool) -> Self {
+        self.merge = merge;
+        if merge {
+            let (tx, rx) = mpsc::channel();
+            self.merge_tx = Mutex::new(Some(tx));
+            self.merge_rx = Mutex::new(Some(rx));
+            Arc::make_mut(&mut self.record_opts).include_source = true;
         }
+        self
+    }
+
+    pub fn with_per_host_dirs(mut self, per_host_dirs: bool) -> Self {
+        self.per_host_dirs = per_host_dirs;
+        self
+    }
+
+    pub fn with_reports(mut self, reports: Vec<ReportKind>) -> Self {
+        self.reports = reports;
+        self
+    }
+
+    pub fn with_raw_tables(mut self, raw_tables: Vec<glob::Pattern>) -> Self {
+        self.raw_tables = raw_tables;
+        self
+    }
+
+    pub fn is_raw(&self) -> bool {
+        !self.raw_tables.is_empty()
+    }
+
+    pub fn is_raw_table(&self, table: &str) -> bool {
+        self.raw_tables.iter().any(|p| p.matches(table))
+    }
+
+    pub fn with_archive(mut self, archive: Option<ReportArchive>, archive_only: bool) -> Self {
+        self.archive = archive;
+        self.archive_only = archive.is_some() && archive_only;
+        self
+    }
+
+    pub fn with_csv_comments(mut self, csv_comments: bool) -> Self {
+        self.csv_comments = csv_comments;
+        self
+    }
+
+    pub fn with_json_envelope(mut self, json_envelope: bool) -> Self {
+        self.json_envelope = json_envelope;
+        self
+    }
+
+    // The hook sees every record that passed the record options, in the order written.
+    pub fn with_record_hook(mut self, record_hook: Option<RecordHook>) -> Self {
+        self.record_hook = record_hook.map(|hook| Arc::new(Mutex::new(hook)));
+        self
+    }
+
+    pub fn with_count_only(mut self, count_only: bool) -> Self {
+        self.count_only = count_only;
+        self
+    }
+
+    pub fn is_count_only(&self) -> bool {
+        self.count_only
+    }
+
+    // there are report files to list
+    pub fn writes_files(&self) -> bool {
+        self.report_type == ReportOutput::ToFile && !self.count_only
+    }
+
+    pub fn is_report_enabled(&self, kind: ReportKind) -> bool {
+        self.reports.is_empty() || self.reports.contains(&kind)
     }
 
     pub fn get_report_type(&self) -> ReportOutput {
@@ -98,17 +560,20 @@ impl ReportProducer {
         date_time_now: DateTime<Utc>,
         ext: &str,
         edb_database_state: Option<DbState>,
+        db_tag: Option<&str>,
     ) -> PathBuf {
         let status = if self.is_db_dirty(edb_database_state) {
             "_dirty"
         } else {
             ""
         };
+        let db_tag = db_tag.map(|t| format!("_{t}")).unwrap_or_default();
         self.dir.join(format!(
-            "{}_{}_{}{}.{}",
+            "{}_{}_{}{}{}.{}",
             recovered_hostname,
             report_suffix,
             date_time_now.format("%Y%m%d_%H%M%S%.f"),
+            db_tag,
             status,
             ext
         ))
@@ -116,361 +581,3384 @@ impl ReportProducer {
 
     pub fn new_report(
         &self,
-        _dbpath: &Path,
+        dbpath: &Path,
         recovered_hostname: &str,
         report_suffix: &str,
         edb_database_state: Option<DbState>,
-    ) -> Result<(PathBuf, Box<dyn Report>), SimpleError> {
-        let ext = match self.format {
-            ReportFormat::Json => "json",
-            ReportFormat::Csv => "csv",
+    ) -> Result<(Vec<PathBuf>, Box<dyn Report>), SimpleError> {
+        let recovered_hostname = match self.hostname.as_deref() {
+            Some(hostname) => hostname,
+            None if recovered_hostname.trim().is_empty() => UNKNOWN_HOSTNAME,
+            None => recovered_hostname,
         };
-        let date_time_now: DateTime<Utc> = Utc::now();
-        let path = self.get_path_db_status(
-            recovered_hostname,
-            report_suffix,
-            date_time_now,
-            ext,
-            edb_database_state,
-        );
-        let report_suffix = ReportSuffix::get_match(report_suffix);
-        let rep: Box<dyn Report> = match self.format {
-            ReportFormat::Json => {
-                ReportJson::new(&path, self.report_type, report_suffix).map(Box::new)?
+        let mut paths = Vec::new();
+        let mut reps: Vec<Box<dyn Report>> = Vec::new();
+        if self.count_only {
+            // RecordFilter counts the records it passes on
+            reps.push(Box::new(ReportNull));
+        } else {
+            for format in self.formats_of(report_suffix) {
+                let (path, rep) = if self.merge {
+                    self.new_merge_sender(format, report_suffix)?
+                } else {
+                    self.new_format_report(
+                        format,
+                        dbpath,
+                        recovered_hostname,
+                        report_suffix,
+                        edb_database_state,
+                    )?
+                };
+                paths.push(path);
+                reps.push(rep);
             }
-            ReportFormat::Csv => {
-                ReportCsv::new(&path, self.report_type, report_suffix).map(Box::new)?
+        }
+        if !self.writes_files() {
+            paths.clear();
+        }
+        {
+            let mut created = self.created.lock().unwrap();
+            for path in &paths {
+                if !created.contains(path) {
+                    created.push(path.clone());
+                }
             }
+        }
+        // the files of all formats get the same records
+        let records = Arc::new(AtomicUsize::new(0));
+        let summary = ReportSummary {
+            host: recovered_hostname.to_string(),
+            report: report_suffix.to_string(),
+            ..Default::default()
         };
-        Ok((path, rep))
-    }
-}
-
-pub trait Report {
-    fn footer(&mut self) {}
-    fn create_new_row(&mut self);
-    fn insert_str_val(&self, f: &str, s: String);
-    fn insert_int_val(&self, f: &str, n: u64);
-    fn set_field(&self, _: &str) {} // used in csv to generate header
-    fn is_some_val_in_record(&self) -> bool;
-}
-
-// report json
-pub struct ReportJson {
-    f: Box<dyn Write + 'static>,
-    report_output: ReportOutput,
-    report_suffix: Option<ReportSuffix>,
-    values: RefCell<Vec<String>>,
-}
-
-impl ReportJson {
-    pub fn new(
-        path: &Path,
-        report_output: ReportOutput,
-        report_suffix: Option<ReportSuffix>,
-    ) -> Result<Self, SimpleError> {
-        match report_output {
-            ReportOutput::ToFile => {
-                let output: Box<dyn Write> =
-                    Box::new(File::create(path).map_err(|e| SimpleError::new(format!("{e}")))?);
-                Ok(ReportJson {
-                    f: output,
-                    report_output,
-                    report_suffix: None,
-                    values: RefCell::new(Vec::new()),
-                })
-            }
-            ReportOutput::ToStdout => Ok(ReportJson {
-                f: Box::new(BufWriter::new(io::stdout())),
-                report_output,
-                report_suffix,
-                values: RefCell::new(Vec::new()),
-            }),
+        let mut counters = self.counters.lock().unwrap();
+        if paths.is_empty() {
+            counters.push((dbpath.to_path_buf(), summary, records.clone()));
         }
+        for path in &paths {
+            let summary = ReportSummary {
+                path: Some(path.clone()),
+                ..summary.clone()
+            };
+            counters.push((dbpath.to_path_buf(), summary, records.clone()));
+        }
+        drop(counters);
+        let rep: Box<dyn Report> = match reps.len() {
+            1 => reps.pop().unwrap(),
+            _ => Box::new(ReportTee(reps)),
+        };
+        let rep = RecordFilter::new(rep, self.record_opts.clone(), report_suffix, records)
+            .with_source(dbpath, recovered_hostname)
+            .with_hook(self.record_hook.clone())
+            .with_run_write_error(self.write_error.clone());
+        Ok((paths, Box::new(rep)))
     }
 
-    fn escape(s: String) -> String {
-        json_escape(&s)
+    // Records written per host, report type and file, in the order the reports were made.
+    // Databases of the same host writing to the same file (sqlite, xlsx, stdout) are added up.
+    pub fn summary(&self) -> Vec<ReportSummary> {
+        let mut summary: Vec<ReportSummary> = Vec::new();
+        for (_, rep, records) in self.counters.lock().unwrap().iter() {
+            let records = records.load(Ordering::Relaxed);
+            match summary
+                .iter_mut()
+                .find(|s| s.host == rep.host && s.report == rep.report && s.path == rep.path)
+            {
+                Some(s) => {
+                    s.records += records;
+                    s.partial |= rep.partial;
+                }
+                None => summary.push(ReportSummary {
+                    records,
+                    ..rep.clone()
+                }),
+            }
+        }
+        summary
     }
 
-    pub fn write_values(&mut self) {
-        let mut values = self.values.borrow_mut();
-        let len = values.len();
-        let handle = self.f.as_mut();
-        if len > 0 {
-            handle.write_all(b"{").unwrap();
-        }
-        if self.report_output == ReportOutput::ToStdout {
-            handle
-                .write_all(
-                    format!(
-                        "{}:{},",
-                        serde_json::to_string("report_suffix").unwrap(),
-                        self.report_suffix.as_ref().unwrap()
-                    )
-                    .as_bytes(),
-                )
-                .ok();
-        }
-        for i in 0..len {
-            let v = values.index_mut(i);
-            if !v.is_empty() {
-                let last = if i == len - 1 { "" } else { "," };
-                handle.write_all(format!("{v}{last}").as_bytes()).unwrap();
+    // Records written per report of a database; added up per file like summary().
+    pub fn db_summary(&self, dbpath: &Path) -> Vec<ReportSummary> {
+        let mut summary: Vec<ReportSummary> = Vec::new();
+        for (db, rep, records) in self.counters.lock().unwrap().iter() {
+            if db != dbpath {
+                continue;
+            }
+            let records = records.load(Ordering::Relaxed);
+            match summary
+                .iter_mut()
+                .find(|s| s.report == rep.report && s.path == rep.path)
+            {
+                Some(s) => s.records += records,
+                None => summary.push(ReportSummary {
+                    records,
+                    ..rep.clone()
+                }),
             }
         }
-        if len > 0 {
-            handle.write_all(b"}\n").unwrap();
-            values.clear();
-        }
-        handle.flush().unwrap();
+        summary
     }
-}
 
-impl Report for ReportJson {
-    fn footer(&mut self) {
-        self.create_new_row();
+    // The reports of the database hold the records read before it turned out to be corrupted.
+    pub fn mark_partial(&self, dbpath: &Path) {
+        for (db, rep, _) in self.counters.lock().unwrap().iter_mut() {
+            if db == dbpath {
+                rep.partial = true;
+            }
+        }
     }
 
-    fn create_new_row(&mut self) {
-        if !self.values.borrow().is_empty() {
-            self.write_values();
+    fn new_format_report(
+        &self,
+        format: ReportFormat,
+        dbpath: &Path,
+        recovered_hostname: &str,
+        report_suffix: &str,
+        edb_database_state: Option<DbState>,
+    ) -> Result<(PathBuf, Box<dyn Report>), SimpleError> {
+        if matches!(format, ReportFormat::Sqlite | ReportFormat::Xlsx) {
+            return self.new_combined_report(format, report_suffix);
         }
+        let path = self.report_path(
+            format,
+            Some(dbpath),
+            recovered_hostname,
+            report_suffix,
+            edb_database_state,
+        )?;
+        let rep = self.format_report(
+            format,
+            &path,
+            Some(dbpath),
+            recovered_hostname,
+            report_suffix,
+        )?;
+        Ok((path, rep))
     }
 
-    fn insert_str_val(&self, f: &str, s: String) {
-        self.values
-            .borrow_mut()
-            .push(format!("\"{}\":{}", f, ReportJson::escape(s)));
+    // The file of a report; without a database it is the merged report of all of them.
+    fn report_path(
+        &self,
+        format: ReportFormat,
+        dbpath: Option<&Path>,
+        recovered_hostname: &str,
+        report_suffix: &str,
+        edb_database_state: Option<DbState>,
+    ) -> Result<PathBuf, SimpleError> {
+        let ext = match format {
+            ReportFormat::Json | ReportFormat::JsonArray => "json",
+            ReportFormat::Csv => "csv",
+            ReportFormat::Tsv => "tsv",
+            ReportFormat::Xml => "xml",
+            ReportFormat::Yaml => "yaml",
+            ReportFormat::EsBulk => "ndjson",
+            ReportFormat::Html => "html",
+            ReportFormat::Markdown => "md",
+            ReportFormat::Sqlite | ReportFormat::Xlsx => unreachable!(),
+        };
+        let ext = match self.compress {
+            Some(ReportCompression::Gzip) if self.report_type == ReportOutput::ToFile => {
+                format!("{ext}.gz")
+            }
+            _ => ext.to_string(),
+        };
+        let date_time_now: DateTime<Utc> = Utc::now();
+        let db_tag = dbpath.map(db_tag);
+        let path = match &self.filename_template {
+            Some(template) => self.dir.join(template.render(&[
+                ("host", recovered_hostname),
+                ("report", report_suffix),
+                ("date", &date_time_now.format("%Y%m%d").to_string()),
+                ("time", &date_time_now.format("%H%M%S").to_string()),
+                ("dbtag", db_tag.as_deref().unwrap_or_default()),
+                (
+                    "dirty",
+                    if self.is_db_dirty(edb_database_state) {
+                        "dirty"
+                    } else {
+                        ""
+                    },
+                ),
+                ("ext", &ext),
+            ])),
+            None => self.get_path_db_status(
+                recovered_hostname,
+                report_suffix,
+                date_time_now,
+                &ext,
+                edb_database_state,
+                db_tag.as_deref(),
+            ),
+        };
+        let path = match path.file_name() {
+            Some(name) if self.per_host_dirs && self.report_type == ReportOutput::ToFile => {
+                let dir = self.dir.join(recovered_hostname);
+                std::fs::create_dir_all(&dir).map_err(|e| {
+                    SimpleError::new(format!("Can't create \"{}\": {e}", dir.to_string_lossy()))
+                })?;
+                dir.join(name)
+            }
+            _ => path,
+        };
+        self.resolve_path(path, &ext)
     }
 
-    fn insert_int_val(&self, f: &str, n: u64) {
-        self.values.borrow_mut().push(format!("\"{f}\":{n}"));
+    // dbpath and recovered_hostname go into the report metadata, no dbpath - merged report
+    fn format_report(
+        &self,
+        format: ReportFormat,
+        path: &Path,
+        dbpath: Option<&Path>,
+        recovered_hostname: &str,
+        report_suffix: &str,
+    ) -> Result<Box<dyn Report>, SimpleError> {
+        if matches!(format, ReportFormat::Sqlite | ReportFormat::Xlsx) {
+            return self
+                .new_combined_report(format, report_suffix)
+                .map(|(_, rep)| rep);
+        }
+        let report_suffix = ReportSuffix::get_match(report_suffix);
+        // stdout carries the records of all reports, so they can't be wrapped or commented
+        let json_envelope = |r: ReportJson| match self.report_type {
+            ReportOutput::ToFile if self.json_envelope => {
+                r.with_envelope(recovered_hostname, dbpath, self.started)
+            }
+            _ => r,
+        };
+        let csv_options = |r: ReportCsv| {
+            let r = r
+                .with_always_header(self.csv_always_header)
+                .with_multiline(self.csv_multiline);
+            match self.report_type {
+                ReportOutput::ToFile if self.csv_comments => {
+                    r.with_comments(recovered_hostname, dbpath, self.started)
+                }
+                _ => r,
+            }
+        };
+        let rep: Box<dyn Report> = match format {
+            ReportFormat::Json => ReportJson::new(&path, self.report_type, report_suffix, false)
+                .map(|r| Box::new(json_envelope(r)))?,
+            ReportFormat::JsonArray => {
+                ReportJson::new(&path, self.report_type, report_suffix, true)
+                    .map(|r| Box::new(json_envelope(r)))?
+            }
+            ReportFormat::Csv => ReportCsv::new(
+                &path,
+                self.report_type,
+                report_suffix,
+                self.csv_delimiter,
+                self.csv_bom,
+            )
+            .map(|r| Box::new(csv_options(r)))?,
+            ReportFormat::Tsv => {
+                ReportCsv::new(&path, self.report_type, report_suffix, '\t', self.csv_bom)
+                    .map(|r| Box::new(csv_options(r)))?
+            }
+            ReportFormat::Xml => {
+                ReportXml::new(&path, self.report_type, report_suffix).map(Box::new)?
+            }
+            ReportFormat::Yaml => {
+                ReportYaml::new(&path, self.report_type, report_suffix).map(Box::new)?
+            }
+            ReportFormat::EsBulk => ReportJson::new(&path, self.report_type, report_suffix, false)
+                .map(|r| Box::new(r.with_bulk_action(self.es_index.as_deref())))?,
+            ReportFormat::Html => {
+                ReportHtml::new(&path, self.report_type, report_suffix).map(Box::new)?
+            }
+            ReportFormat::Markdown => {
+                ReportMarkdown::new(&path, self.report_type, report_suffix).map(Box::new)?
+            }
+            ReportFormat::Sqlite | ReportFormat::Xlsx => unreachable!(),
+        };
+        Ok(rep)
     }
 
-    fn is_some_val_in_record(&self) -> bool {
-        !self.values.borrow().is_empty()
+    // Hands the records of a database to write_merged_reports instead of writing them.
+    fn new_merge_sender(
+        &self,
+        format: ReportFormat,
+        report_suffix: &str,
+    ) -> Result<(PathBuf, Box<dyn Report>), SimpleError> {
+        let tx = self
+            .merge_tx
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| SimpleError::new("Merged reports are already closed"))?;
+        let mut merged_paths = self.merged_paths.lock().unwrap();
+        let path = match merged_paths
+            .iter()
+            .find(|(s, _, f)| s == report_suffix && *f == format)
+        {
+            Some((_, path, _)) => path.clone(),
+            None => {
+                let path = if matches!(format, ReportFormat::Sqlite | ReportFormat::Xlsx) {
+                    self.combined_report_path(format)?
+                } else {
+                    self.report_path(format, None, MERGED_HOSTNAME, report_suffix, None)?
+                };
+                merged_paths.push((report_suffix.to_string(), path.clone(), format));
+                path
+            }
+        };
+        Ok((
+            path.clone(),
+            Box::new(MergeSender::new(tx, path, report_suffix)),
+        ))
     }
-}
+
+    // Writes the records of all databases into the merged reports until close_merged_reports()
+    // is called and the reports of every database are dropped. Runs on a thread of its own
+    // next to the ones processing the databases.
+    pub fn write_merged_reports(&self) {
+        let rx = match self.merge_rx.lock().unwrap().take() {
+            Some(rx) => rx,
+            None => return,
+        };
+        // sqlite and xlsx report types share a file, so the type is part of the key;
+        // None - the report could not be created, its records are dropped
+        let mut reports: Vec<(PathBuf, String, Option<Box<dyn Report>>)> = Vec::new();
+        for (path, report_suffix, values) in rx {
+            let i = match reports
+                .iter()
+                .position(|(p, s, _)| *p == path && *s == report_suffix)
+            {
+                Some(i) => i,
+                None => {
+                    let format = self
+                        .merged_paths
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .find(|(s, p, _)| *p == path && *s == report_suffix)
+                        .map(|(_, _, f)| *f)
+                        .unwrap_or(self.formats[0]);
+                    let rep = self
+                        .format_report(format, &path, None, MERGED_HOSTNAME, &report_suffix)
+                        .map_err(|e| error!("Can't create \"{}\": {e}", path.to_string_lossy()))
+                        .ok();
+                    reports.push((path, report_suffix, rep));
+                    reports.len() - 1
+                }
+            };
+            if let Some(rep) = &mut reports[i].2 {
+                for (f, v) in values {
+                    v.insert_into(&**rep, &f);
+                }
+                rep.create_new_row();
+            }
+        }
+        for rep in reports.iter_mut().filter_map(|(_, _, rep)| rep.as_mut()) {
+            rep.footer();
+            if let Some(kind) = rep.write_error() {
+                self.write_error.lock().unwrap().get_or_insert(kind);
+            }
+        }
+    }
+
+    // The first error writing a report, once the reports are dropped. BrokenPipe means
+    // stdout was closed, so the records just had nobody to read them.
+    pub fn write_error(&self) -> Option<io::ErrorKind> {
+        *self.write_error.lock().unwrap()
+    }
+
+    pub fn close_merged_reports(&self) {
+        self.merge_tx.lock().unwrap().take();
+    }
+
+    pub fn is_merged(&self) -> bool {
+        self.merge
+    }
+
+    // Applies the clobber policy to a report file about to be created. The extension
+    // (e.g. "json.gz") is kept at the end of renamed files. Report files are only created
+    // with their first record, so the names handed out are kept: exists() can't tell that
+    // another report of the run already resolved to the same file.
+    fn resolve_path(&self, path: PathBuf, ext: &str) -> Result<PathBuf, SimpleError> {
+        if self.report_type == ReportOutput::ToStdout {
+            return Ok(path);
+        }
+        let mut resolved = self.resolved_paths.lock().unwrap();
+        let taken_in_run = resolved.contains(&path);
+        let path = if !taken_in_run && (!path.exists() || self.clobber == ClobberPolicy::Overwrite)
+        {
+            path
+        } else if self.clobber == ClobberPolicy::Rename {
+            let name = path.file_name().unwrap().to_string_lossy();
+            let stem = name.strip_suffix(&format!(".{ext}")).unwrap_or(&name);
+            (1..)
+                .map(|n| path.with_file_name(format!("{stem}_{n}.{ext}")))
+                .find(|p| !p.exists() && !resolved.contains(p))
+                .ok_or_else(|| SimpleError::new("No free report file name"))?
+        } else if taken_in_run {
+            return Err(SimpleError::new(format!(
+                "Report file \"{}\" is already written by another report (add {{dbtag}} to --filename-template or use --no-clobber)",
+                path.to_string_lossy()
+            )));
+        } else {
+            return Err(SimpleError::new(format!(
+                "Report file \"{}\" already exists (use --overwrite to replace it or --no-clobber to pick another name)",
+                path.to_string_lossy()
+            )));
+        };
+        resolved.push(path.clone());
+        Ok(path)
+    }
+
+    fn combined_report_path(&self, format: ReportFormat) -> Result<PathBuf, SimpleError> {
+        let mut combined_paths = self.combined_paths.lock().unwrap();
+        if let Some((_, path)) = combined_paths.iter().find(|(f, _)| *f == format) {
+            return Ok(path.clone());
+        }
+        let ext = match format {
+            ReportFormat::Sqlite => "sqlite",
+            _ => "xlsx",
+        };
+        let path = self.dir.join(format!(
+            "SIDR_Reports_{}.{}",
+            self.started.format("%Y%m%d_%H%M%S%.f"),
+            ext
+        ));
+        let path = self.resolve_path(path, ext)?;
+        combined_paths.push((format, path.clone()));
+        Ok(path)
+    }
+
+    // All reports of the run go to one file, a table (sqlite) or worksheet (xlsx) per report type.
+    fn new_combined_report(
+        &self,
+        format: ReportFormat,
+        report_suffix: &str,
+    ) -> Result<(PathBuf, Box<dyn Report>), SimpleError> {
+        if self.report_type == ReportOutput::ToStdout {
+            let format = format!("{format:?}").to_lowercase();
+            return Err(SimpleError::new(format!(
+                "{format} format can only be written to file"
+            )));
+        }
+        let path = self.combined_report_path(format)?;
+        let rep: Box<dyn Report> = match format {
+            ReportFormat::Sqlite => {
+                let mut sqlite = self.sqlite.lock().unwrap();
+                let db = match &*sqlite {
+                    Some(db) => db.clone(),
+                    None => sqlite.insert(SqliteDb::open(&path)?).clone(),
+                };
+                Box::new(ReportSqlite::with_db(db, report_suffix)?)
+            }
+            _ => Box::new(ReportXlsx::new(self.xlsx.clone(), report_suffix)),
+        };
+        Ok((path, rep))
+    }
+
+    // Writes out what is kept in memory until all databases are processed (the xlsx workbook)
+    // and the manifest of the report files. All reports must be dropped by now.
+    pub fn finish(&self) -> Result<(), SimpleError> {
+        if self
+            .combined_paths
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(f, _)| *f == ReportFormat::Xlsx)
+        {
+            let mut book = self.xlsx.lock().unwrap();
+            if !book.sheets.is_empty() {
+                let path = self.combined_report_path(ReportFormat::Xlsx)?;
+                book.workbook.save(&path).map_err(|e| {
+                    SimpleError::new(format!("Can't save \"{}\": {e}", path.to_string_lossy()))
+                })?;
+            }
+        }
+        if self.writes_files() {
+            self.write_manifest()?;
+            if let Some(archive) = self.archive {
+                self.write_archive(archive)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The report files of the run and the manifest, as far as they were written.
+    fn archive_entries(&self) -> Vec<PathBuf> {
+        let mut entries: Vec<PathBuf> = self
+            .created
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|p| p.exists())
+            .cloned()
+            .collect();
+        entries.push(self.dir.join(MANIFEST_FILE));
+        entries
+    }
+
+    // HOSTNAME_SIDR_DateTime.ext in the output directory, All_Hosts when the run saw several.
+    fn archive_path(&self, ext: &str) -> Result<PathBuf, SimpleError> {
+        let mut hosts: Vec<String> = self.summary().into_iter().map(|s| s.host).collect();
+        hosts.sort();
+        hosts.dedup();
+        let host = match hosts.as_slice() {
+            [host] => host.as_str(),
+            _ => MERGED_HOSTNAME,
+        };
+        let path = self.dir.join(format!(
+            "{host}_SIDR_{}.{ext}",
+            self.started.format("%Y%m%d_%H%M%S")
+        ));
+        self.resolve_path(path, ext)
+    }
+
+    // Entries are named relative to the output directory, so --per-host-dirs is kept.
+    fn write_archive(&self, archive: ReportArchive) -> Result<(), SimpleError> {
+        let path = self.archive_path(match archive {
+            ReportArchive::Zip => "zip",
+            ReportArchive::TarGz => "tar.gz",
+        })?;
+        let entries: Vec<(PathBuf, String)> = self
+            .archive_entries()
+            .into_iter()
+            .map(|p| {
+                let name = p
+                    .strip_prefix(&self.dir)
+                    .unwrap_or(&p)
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                (p, name)
+            })
+            .collect();
+        let f = File::create(&path).map_err(|e| archive_error(&path, e))?;
+        match archive {
+            ReportArchive::Zip => write_zip(f, &entries),
+            ReportArchive::TarGz => write_tar_gz(f, &entries),
+        }
+        .map_err(|e| archive_error(&path, e))?;
+        info!("Reports packed into \"{}\"", path.to_string_lossy());
+        if self.archive_only {
+            self.remove_archived(&entries)?;
+        }
+        Ok(())
+    }
+
+    // The directories --per-host-dirs created are removed too once they are empty.
+    fn remove_archived(&self, entries: &[(PathBuf, String)]) -> Result<(), SimpleError> {
+        for (entry, _) in entries {
+            std::fs::remove_file(entry).map_err(|e| {
+                SimpleError::new(format!("Can't remove \"{}\": {e}", entry.to_string_lossy()))
+            })?;
+            if let Some(dir) = entry.parent().filter(|d| *d != self.dir) {
+                let _ = std::fs::remove_dir(dir);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_manifest(&self) -> Result<(), SimpleError> {
+        let summary = self.summary();
+        let mut reports = serde_json::Map::new();
+        for path in self.created.lock().unwrap().iter() {
+            // an xlsx workbook without sheets is never saved
+            if !path.exists() {
+                continue;
+            }
+            let (size, sha256) = file_sha256(path).map_err(|e| {
+                SimpleError::new(format!("Can't hash \"{}\": {e}", path.to_string_lossy()))
+            })?;
+            // relative to the output directory, for --per-host-dirs
+            let name = path
+                .strip_prefix(&self.dir)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .into_owned();
+            let partial = summary
+                .iter()
+                .any(|s| s.partial && s.path.as_ref() == Some(path));
+            reports.insert(
+                name,
+                serde_json::json!({ "size": size, "sha256": sha256, "partial": partial }),
+            );
+        }
+        let manifest = serde_json::json!({
+            "tool": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "started": format_date_time(self.started),
+            "reports": reports,
+        });
+        let path = self.dir.join(MANIFEST_FILE);
+        std::fs::write(&path, serde_json::to_string_pretty(&manifest).unwrap()).map_err(|e| {
+            SimpleError::new(format!("Can't write \"{}\": {e}", path.to_string_lossy()))
+        })
+    }
+}
+
+fn archive_error(path: &Path, e: impl Display) -> SimpleError {
+    SimpleError::new(format!("Can't write \"{}\": {e}", path.to_string_lossy()))
+}
+
+fn write_zip(f: File, entries: &[(PathBuf, String)]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut zip = zip::ZipWriter::new(f);
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (path, name) in entries {
+        zip.start_file(name, options)?;
+        io::copy(&mut File::open(path)?, &mut zip)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+fn write_tar_gz(f: File, entries: &[(PathBuf, String)]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tar = tar::Builder::new(GzEncoder::new(BufWriter::new(f), Compression::default()));
+    for (path, name) in entries {
+        tar.append_path_with_name(path, name)?;
+    }
+    tar.into_inner()?.finish()?.flush()?;
+    Ok(())
+}
+
+// Tells apart the reports of databases of the same host made at the same time.
+fn db_tag(dbpath: &Path) -> String {
+    let hash = format!("{:x}", Sha256::digest(dbpath.to_string_lossy().as_bytes()));
+    hash[..8].to_string()
+}
+
+fn file_sha256(path: &Path) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+// Report files are created by the first write, so a report type without records
+// (e.g. no activity history in the database) leaves no empty file behind.
+fn create_report_file(path: &Path) -> Box<dyn Write> {
+    Box::new(LazyReportFile {
+        path: path.to_path_buf(),
+        f: None,
+    })
+}
+
+struct LazyReportFile {
+    path: PathBuf,
+    f: Option<Box<dyn Write>>,
+}
+
+impl Write for LazyReportFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let f = match self.f.take() {
+            Some(f) => f,
+            None => open_report_file(&self.path)?,
+        };
+        self.f.insert(f).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.f {
+            Some(f) => f.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// Reports with a .gz extension are compressed on the fly. The encoder writes the gzip trailer
+// when it is dropped, which happens right after the report's Drop has written the footer.
+fn open_report_file(path: &Path) -> io::Result<Box<dyn Write>> {
+    let f = File::create(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Can't create \"{}\": {e}", path.to_string_lossy()),
+        )
+    })?;
+    let f = BufWriter::new(f);
+    if path.extension().map_or(false, |ext| ext == "gz") {
+        Ok(Box::new(GzEncoder::new(f, Compression::default())))
+    } else {
+        Ok(Box::new(f))
+    }
+}
+
+// The lock is held for the whole report; it is reentrant, so the reports of a database
+// (all created by the same thread) can hold it at the same time.
+fn stdout_writer() -> Box<dyn Write> {
+    Box::new(BufWriter::new(io::stdout().lock()))
+}
+
+// Reports written to stdout share the stream, so every record is pushed out whole
+// before another report writes. Report files are buffered and flushed by footer.
+fn flush_record(f: &mut dyn Write, report_output: ReportOutput) -> io::Result<()> {
+    if report_output == ReportOutput::ToStdout {
+        f.flush()
+    } else {
+        Ok(())
+    }
+}
+
+// The first error writing a report, which stops the report taking records. It is logged
+// unless stdout was closed (e.g. output piped into `head`), which just means nobody reads the
+// rest of the records; what to do about it is up to the caller, see ReportProducer::write_error.
+#[derive(Default)]
+struct WriteError(Cell<Option<io::ErrorKind>>);
+
+impl WriteError {
+    fn check(&self, result: io::Result<()>) {
+        if let Err(e) = result {
+            if self.0.get().is_none() {
+                if e.kind() != io::ErrorKind::BrokenPipe {
+                    error!("Failed to write report: {e}");
+                }
+                self.0.set(Some(e.kind()));
+            }
+        }
+    }
+
+    fn get(&self) -> Option<io::ErrorKind> {
+        self.0.get()
+    }
+}
+
+pub trait Report {
+    fn footer(&mut self) {}
+    fn create_new_row(&mut self);
+    // pushes the records written so far out of the buffers, the one being built stays
+    fn flush(&mut self) {}
+    fn insert_str_val(&self, f: &str, s: String);
+    fn insert_int_val(&self, f: &str, n: u64);
+    fn insert_i64_val(&self, f: &str, n: i64) {
+        self.insert_str_val(f, n.to_string());
+    }
+    fn insert_bool_val(&self, f: &str, b: bool) {
+        self.insert_str_val(f, b.to_string());
+    }
+    fn insert_float_val(&self, f: &str, v: f64) {
+        self.insert_str_val(f, v.to_string());
+    }
+    // ISO 8601 UTC with the 100ns precision of FILETIME, e.g. 2023-03-07T01:52:44.1234567Z
+    fn insert_datetime_val(&self, f: &str, dt: DateTime<Utc>) {
+        self.insert_str_val(f, format_date_time(dt));
+    }
+    fn insert_blob_val(&self, f: &str, b: &[u8]) {
+        self.insert_str_val(f, BlobEncoding::Base64.encode(b));
+    }
+    // a binary SID (see is_sid)
+    fn insert_sid_val(&self, f: &str, b: &[u8]) {
+        self.insert_str_val(f, sid_to_string(b));
+    }
+    // no more records are written (--limit), so the database needn't be read any further
+    fn is_full(&self) -> bool {
+        false
+    }
+    // the first error writing the report, see WriteError
+    fn write_error(&self) -> Option<io::ErrorKind> {
+        None
+    }
+    fn set_field(&self, _: &str) {} // used in csv to generate header
+    fn is_some_val_in_record(&self) -> bool;
+}
+
+// stands in for a report that is not generated
+pub struct ReportNull;
+
+impl Report for ReportNull {
+    fn create_new_row(&mut self) {}
+    fn is_full(&self) -> bool {
+        true
+    }
+    fn insert_str_val(&self, _: &str, _: String) {}
+    fn insert_int_val(&self, _: &str, _: u64) {}
+    fn is_some_val_in_record(&self) -> bool {
+        false
+    }
+}
+
+// writes the records into several reports, one per format
+pub struct ReportTee(pub Vec<Box<dyn Report>>);
+
+impl Report for ReportTee {
+    fn footer(&mut self) {
+        self.0.iter_mut().for_each(|r| r.footer());
+    }
+    fn create_new_row(&mut self) {
+        self.0.iter_mut().for_each(|r| r.create_new_row());
+    }
+    fn flush(&mut self) {
+        self.0.iter_mut().for_each(|r| r.flush());
+    }
+    fn insert_str_val(&self, f: &str, s: String) {
+        self.0.iter().for_each(|r| r.insert_str_val(f, s.clone()));
+    }
+    fn insert_int_val(&self, f: &str, n: u64) {
+        self.0.iter().for_each(|r| r.insert_int_val(f, n));
+    }
+    fn insert_i64_val(&self, f: &str, n: i64) {
+        self.0.iter().for_each(|r| r.insert_i64_val(f, n));
+    }
+    fn insert_bool_val(&self, f: &str, b: bool) {
+        self.0.iter().for_each(|r| r.insert_bool_val(f, b));
+    }
+    fn insert_float_val(&self, f: &str, v: f64) {
+        self.0.iter().for_each(|r| r.insert_float_val(f, v));
+    }
+    fn insert_datetime_val(&self, f: &str, dt: DateTime<Utc>) {
+        self.0.iter().for_each(|r| r.insert_datetime_val(f, dt));
+    }
+    fn insert_blob_val(&self, f: &str, b: &[u8]) {
+        self.0.iter().for_each(|r| r.insert_blob_val(f, b));
+    }
+    fn insert_sid_val(&self, f: &str, b: &[u8]) {
+        self.0.iter().for_each(|r| r.insert_sid_val(f, b));
+    }
+    fn is_full(&self) -> bool {
+        self.0.iter().all(|r| r.is_full())
+    }
+    fn write_error(&self) -> Option<io::ErrorKind> {
+        self.0.iter().find_map(|r| r.write_error())
+    }
+    fn set_field(&self, f: &str) {
+        self.0.iter().for_each(|r| r.set_field(f));
+    }
+    fn is_some_val_in_record(&self) -> bool {
+        self.0.iter().any(|r| r.is_some_val_in_record())
+    }
+}
+
+// report json
+pub struct ReportJson {
+    f: Box<dyn Write + 'static>,
+    report_output: ReportOutput,
+    report_suffix: Option<ReportSuffix>,
+    json_array: bool,            // write [rec1,rec2,...] instead of JSONL
+    bulk_action: Option<String>, // Elasticsearch _bulk action line written before every record
+    // opens the object with the run metadata the records array is written into
+    envelope: Option<String>,
+    first_record: Cell<bool>,
+    finished: bool,
+    values: RefCell<Vec<String>>,
+    error: WriteError,    // the first failed write
+    some_val: Cell<bool>, // a value of the record is not an empty string
+}
+
+impl ReportJson {
+    pub fn new(
+        path: &Path,
+        report_output: ReportOutput,
+        report_suffix: Option<ReportSuffix>,
+        json_array: bool,
+    ) -> Result<Self, SimpleError> {
+        let output = match report_output {
+            ReportOutput::ToFile => create_report_file(path),
+            ReportOutput::ToStdout => stdout_writer(),
+        };
+        Ok(ReportJson::with_writer(
+            output,
+            report_output,
+            report_suffix,
+            json_array,
+        ))
+    }
+
+    // Writes the report into f (e.g. a Vec<u8> or a socket); report_output only decides
+    // whether records are tagged with their report type and flushed one by one.
+    pub fn with_writer(
+        f: Box<dyn Write>,
+        report_output: ReportOutput,
+        report_suffix: Option<ReportSuffix>,
+        json_array: bool,
+    ) -> Self {
+        ReportJson {
+            f,
+            report_output,
+            report_suffix,
+            json_array,
+            bulk_action: None,
+            envelope: None,
+            first_record: Cell::new(true),
+            finished: false,
+            values: RefCell::new(Vec::new()),
+            error: WriteError::default(),
+            some_val: Cell::new(false),
+        }
+    }
+
+    // {"tool":"sidr",...,"records":[rec1,rec2,...]}; source_db is null for merged reports.
+    pub fn with_envelope(
+        mut self,
+        host: &str,
+        source_db: Option<&Path>,
+        generated: DateTime<Utc>,
+    ) -> Self {
+        let source_db = source_db.map_or("null".to_string(), |p| json_escape(&p.to_string_lossy()));
+        self.envelope = Some(format!(
+            "{{\"tool\":\"sidr\",\"version\":{},\"host\":{},\"source_db\":{source_db},\"generated\":{},\"records\":",
+            json_escape(env!("CARGO_PKG_VERSION")),
+            json_escape(host),
+            json_escape(&generated.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        ));
+        self
+    }
+
+    fn is_array(&self) -> bool {
+        self.json_array || self.envelope.is_some()
+    }
+
+    pub fn with_bulk_action(mut self, es_index: Option<&str>) -> Self {
+        self.bulk_action = Some(match es_index {
+            Some(index) => format!("{{\"index\":{{\"_index\":{}}}}}", json_escape(index)),
+            None => "{\"index\":{}}".to_string(),
+        });
+        self
+    }
+
+    fn escape(s: String) -> String {
+        json_escape(&s)
+    }
+
+    fn push(&self, value: String, is_empty: bool) {
+        self.values.borrow_mut().push(value);
+        if !is_empty {
+            self.some_val.set(true);
+        }
+    }
+
+    pub fn write_values(&mut self) -> io::Result<()> {
+        let mut values = self.values.borrow_mut();
+        if values.is_empty() {
+            return Ok(());
+        }
+        let mut line = String::new();
+        if self.first_record.get() {
+            line.push_str(self.envelope.as_deref().unwrap_or_default());
+        }
+        if self.is_array() {
+            line.push_str(if self.first_record.get() {
+                "[\n"
+            } else {
+                ",\n"
+            });
+        }
+        self.first_record.set(false);
+        if let Some(action) = &self.bulk_action {
+            line.push_str(action);
+            line.push('\n');
+        }
+        line.push('{');
+        if self.report_output == ReportOutput::ToStdout {
+            line.push_str(&format!(
+                "{}:{},",
+                serde_json::to_string("report_suffix").unwrap(),
+                self.report_suffix.as_ref().unwrap()
+            ));
+        }
+        line.push_str(&values.join(","));
+        line.push_str(if self.is_array() { "}" } else { "}\n" });
+        // the record is consumed even if writing fails, so it can't leak into the next one
+        values.clear();
+        self.some_val.set(false);
+        self.f.write_all(line.as_bytes())?;
+        flush_record(self.f.as_mut(), self.report_output)
+    }
+}
+
+impl Report for ReportJson {
+    fn footer(&mut self) {
+        self.create_new_row();
+        // a report without records doesn't create its file for the footer alone
+        if self.is_array() && !self.finished && !self.first_record.get() {
+            let mut end = String::from("\n]");
+            if self.envelope.is_some() {
+                end.push('}');
+            }
+            end.push('\n');
+            self.error.check(self.f.write_all(end.as_bytes()));
+            self.finished = true;
+        }
+        self.error.check(self.f.flush());
+    }
+
+    fn create_new_row(&mut self) {
+        if self.is_some_val_in_record() {
+            let result = self.write_values();
+            self.error.check(result);
+        } else {
+            // a record of empty strings only is left out, as in csv
+            self.values.borrow_mut().clear();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.error.check(self.f.flush());
+    }
+
+    fn insert_str_val(&self, f: &str, s: String) {
+        let is_empty = s.is_empty();
+        self.push(format!("\"{}\":{}", f, ReportJson::escape(s)), is_empty);
+    }
+
+    fn insert_int_val(&self, f: &str, n: u64) {
+        self.push(format!("\"{f}\":{n}"), false);
+    }
+
+    fn insert_i64_val(&self, f: &str, n: i64) {
+        self.push(format!("\"{f}\":{n}"), false);
+    }
+
+    fn insert_bool_val(&self, f: &str, b: bool) {
+        self.push(format!("\"{f}\":{b}"), false);
+    }
+
+    fn insert_float_val(&self, f: &str, v: f64) {
+        // NaN and infinities have no JSON representation
+        let v = if v.is_finite() {
+            serde_json::to_string(&v).unwrap()
+        } else {
+            "null".to_string()
+        };
+        self.push(format!("\"{f}\":{v}"), false);
+    }
+
+    fn write_error(&self) -> Option<io::ErrorKind> {
+        self.error.get()
+    }
+
+    fn is_some_val_in_record(&self) -> bool {
+        self.some_val.get()
+    }
+}
 
 impl Drop for ReportJson {
     fn drop(&mut self) {
         self.footer();
     }
-}
+}
+
+// report csv
+pub struct ReportCsv {
+    f: Box<dyn Write + 'static>,
+    report_output: ReportOutput,
+    report_suffix: Option<ReportSuffix>,
+    delimiter: char, // '\t' produces TSV: values are not quoted, tabs are escaped instead
+    bom: bool,       // lets Excel detect UTF-8, only written to files
+    always_header: bool, // write the header of the fields declared by set_field without records
+    multiline: bool, // keep CR/LF in the quoted value instead of escaping them
+    comments: Option<String>, // '#' lines with the run metadata written before the header
+    first_record: Cell<bool>,
+    values: RefCell<Vec<(String /*field*/, String /*value*/)>>,
+    error: WriteError, // the first failed write
+}
+
+impl ReportCsv {
+    pub fn new(
+        f: &Path,
+        report_output: ReportOutput,
+        report_suffix: Option<ReportSuffix>,
+        delimiter: char,
+        bom: bool,
+    ) -> Result<Self, SimpleError> {
+        let output = match report_output {
+            ReportOutput::ToFile => create_report_file(f),
+            ReportOutput::ToStdout => stdout_writer(),
+        };
+        Ok(ReportCsv::with_writer(
+            output,
+            report_output,
+            report_suffix,
+            delimiter,
+            bom && report_output == ReportOutput::ToFile,
+        ))
+    }
+
+    // Writes the report into f, see ReportJson::with_writer.
+    pub fn with_writer(
+        f: Box<dyn Write>,
+        report_output: ReportOutput,
+        report_suffix: Option<ReportSuffix>,
+        delimiter: char,
+        bom: bool,
+    ) -> Self {
+        ReportCsv {
+            f,
+            report_output,
+            report_suffix,
+            delimiter,
+            bom,
+            always_header: false,
+            multiline: false,
+            comments: None,
+            first_record: Cell::new(true),
+            values: RefCell::new(Vec::new()),
+            error: WriteError::default(),
+        }
+    }
+
+    // source_db is None for merged reports.
+    pub fn with_comments(
+        mut self,
+        host: &str,
+        source_db: Option<&Path>,
+        generated: DateTime<Utc>,
+    ) -> Self {
+        self.comments = Some(format!(
+            "# tool=sidr version={} host={host} source={} generated={}\n",
+            env!("CARGO_PKG_VERSION"),
+            source_db.map_or("-".into(), |p| p.to_string_lossy()),
+            generated.to_rfc3339_opts(SecondsFormat::Secs, true)
+        ));
+        self
+    }
+
+    pub fn with_always_header(mut self, always_header: bool) -> Self {
+        self.always_header = always_header;
+        self
+    }
+
+    pub fn with_multiline(mut self, multiline: bool) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    // String values are always quoted, so the delimiter can't split them and quotes are doubled
+    // as RFC 4180 requires. CR/LF are written as \r/\n to keep every record on a single line
+    // for line based tools, unless multiline keeps them in the quoted value as RFC 4180 allows;
+    // numeric values never need quoting and stay bare.
+    fn escape(&self, s: String) -> String {
+        let s = s.replace('\"', "\"\"");
+        if self.multiline {
+            s
+        } else {
+            s.replace('\n', "\\n").replace('\r', "\\r")
+        }
+    }
+
+    fn escape_tsv(s: String) -> String {
+        s.replace('\t', "\\t")
+            .replace("\n", "\\n")
+            .replace("\r", "\\r")
+    }
+
+    fn is_tsv(&self) -> bool {
+        self.delimiter == '\t'
+    }
+
+    pub fn write_header(&mut self) -> io::Result<()> {
+        let mut line = String::new();
+        if self.bom {
+            line.push('\u{FEFF}');
+        }
+        if let Some(comments) = &self.comments {
+            line.push_str(comments);
+        }
+        if self.report_output == ReportOutput::ToStdout {
+            line.push_str(&format!("\nReportSuffix{}", self.delimiter));
+        }
+        let values = self.values.borrow();
+        let fields: Vec<&str> = values.iter().map(|v| v.0.as_str()).collect();
+        line.push_str(&fields.join(&self.delimiter.to_string()));
+        self.f.write_all(line.as_bytes())
+    }
+
+    pub fn write_values(&mut self) -> io::Result<()> {
+        let mut line = String::from("\n");
+        if self.report_output == ReportOutput::ToStdout {
+            line.push_str(&format!(
+                "{}{}",
+                self.report_suffix.as_ref().unwrap(),
+                self.delimiter
+            ));
+        }
+        // the record is consumed even if writing fails, so it can't leak into the next one
+        let row: Vec<String> = self
+            .values
+            .borrow_mut()
+            .iter_mut()
+            .map(|v| std::mem::take(&mut v.1))
+            .collect();
+        line.push_str(&row.join(&self.delimiter.to_string()));
+        self.f.write_all(line.as_bytes())?;
+        flush_record(self.f.as_mut(), self.report_output)
+    }
+
+    pub fn update_field_with_value(&self, f: &str, v: String) {
+        let mut values = self.values.borrow_mut();
+        if let Some(found) = values.iter_mut().find(|i| i.0 == f) {
+            found.1 = v;
+        } else {
+            values.push((f.into(), v));
+        }
+    }
+}
+
+impl Report for ReportCsv {
+    fn footer(&mut self) {
+        self.create_new_row();
+        // the declared fields are kept with empty values, so they make the header
+        if self.always_header && self.first_record.get() && !self.values.borrow().is_empty() {
+            let result = self.write_header();
+            self.error.check(result);
+            self.first_record.set(false);
+        }
+        self.error.check(self.f.flush());
+    }
+
+    fn create_new_row(&mut self) {
+        // at least 1 value was recorded?
+        if self.is_some_val_in_record() {
+            if self.first_record.get() {
+                let result = self.write_header();
+                self.error.check(result);
+                self.first_record.set(false);
+            }
+            let result = self.write_values();
+            self.error.check(result);
+        }
+    }
+
+    fn flush(&mut self) {
+        self.error.check(self.f.flush());
+    }
+
+    fn insert_str_val(&self, f: &str, s: String) {
+        let v = if self.is_tsv() {
+            ReportCsv::escape_tsv(s)
+        } else {
+            format!("\"{}\"", self.escape(s))
+        };
+        self.update_field_with_value(f, v);
+    }
+
+    fn insert_int_val(&self, f: &str, n: u64) {
+        self.update_field_with_value(f, n.to_string());
+    }
+
+    fn insert_i64_val(&self, f: &str, n: i64) {
+        self.update_field_with_value(f, n.to_string());
+    }
+
+    fn insert_bool_val(&self, f: &str, b: bool) {
+        self.update_field_with_value(f, b.to_string());
+    }
+
+    fn insert_float_val(&self, f: &str, v: f64) {
+        if v.is_finite() {
+            self.update_field_with_value(f, v.to_string());
+        }
+    }
+
+    fn set_field(&self, f: &str) {
+        // set field with empty value to record field name
+        self.update_field_with_value(f, "".to_string());
+    }
+
+    fn write_error(&self) -> Option<io::ErrorKind> {
+        self.error.get()
+    }
+
+    fn is_some_val_in_record(&self) -> bool {
+        self.values.borrow().iter().any(|i| !i.1.is_empty())
+    }
+}
+
+impl Drop for ReportCsv {
+    fn drop(&mut self) {
+        self.footer();
+    }
+}
+
+// report xml
+pub struct ReportXml {
+    f: Box<dyn Write + 'static>,
+    report_output: ReportOutput,
+    report_suffix: Option<ReportSuffix>,
+    started: bool, // xml declaration and root element are written
+    finished: bool,
+    values: RefCell<Vec<(String /*element*/, String /*value*/)>>,
+    error: WriteError, // the first failed write
+}
+
+impl ReportXml {
+    pub fn new(
+        path: &Path,
+        report_output: ReportOutput,
+        report_suffix: Option<ReportSuffix>,
+    ) -> Result<Self, SimpleError> {
+        let output: Box<dyn Write> = match report_output {
+            ReportOutput::ToFile => create_report_file(path),
+            ReportOutput::ToStdout => stdout_writer(),
+        };
+        Ok(ReportXml {
+            f: output,
+            report_output,
+            report_suffix,
+            started: false,
+            finished: false,
+            values: RefCell::new(Vec::new()),
+            error: WriteError::default(),
+        })
+    }
+
+    // column names are mostly valid element names already, replace whatever is not
+    fn element_name(f: &str) -> String {
+        let mut name: String = f
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || matches!(c, '_' | '-' | '.') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        if !name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            name.insert(0, '_');
+        }
+        name
+    }
+
+    fn start(&mut self, out: &mut String) {
+        if !self.started {
+            let report_type = self.report_suffix.as_ref().map_or("", |s| s.name());
+            out.push_str(&format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<report type=\"{report_type}\">\n"
+            ));
+            self.started = true;
+        }
+    }
+
+    pub fn write_values(&mut self) -> io::Result<()> {
+        let mut out = String::new();
+        self.start(&mut out);
+        out.push_str("<record>\n");
+        for (element, value) in self.values.borrow_mut().drain(..) {
+            out.push_str(&format!("  <{element}>{value}</{element}>\n"));
+        }
+        out.push_str("</record>\n");
+        self.f.write_all(out.as_bytes())?;
+        flush_record(self.f.as_mut(), self.report_output)
+    }
+}
+
+impl Report for ReportXml {
+    fn footer(&mut self) {
+        self.create_new_row();
+        // a report without records doesn't create its file for the footer alone
+        if self.started && !self.finished {
+            self.error.check(self.f.write_all(b"</report>\n"));
+            self.finished = true;
+        }
+        self.error.check(self.f.flush());
+    }
+
+    fn create_new_row(&mut self) {
+        if self.is_some_val_in_record() {
+            let result = self.write_values();
+            self.error.check(result);
+        } else {
+            // a record of empty strings only is left out, as in json and csv
+            self.values.borrow_mut().clear();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.error.check(self.f.flush());
+    }
+
+    fn insert_str_val(&self, f: &str, s: String) {
+        self.values
+            .borrow_mut()
+            .push((ReportXml::element_name(f), xml_escape(&s)));
+    }
+
+    fn insert_int_val(&self, f: &str, n: u64) {
+        self.values
+            .borrow_mut()
+            .push((ReportXml::element_name(f), n.to_string()));
+    }
+
+    fn write_error(&self) -> Option<io::ErrorKind> {
+        self.error.get()
+    }
+
+    fn is_some_val_in_record(&self) -> bool {
+        self.values.borrow().iter().any(|v| !v.1.is_empty())
+    }
+}
+
+impl Drop for ReportXml {
+    fn drop(&mut self) {
+        self.footer();
+    }
+}
+
+// report yaml
+pub struct ReportYaml {
+    f: Box<dyn Write + 'static>,
+    report_output: ReportOutput,
+    report_suffix: Option<ReportSuffix>,
+    values: RefCell<Vec<(String /*field*/, serde_yaml::Value)>>,
+    error: WriteError, // the first failed write
+}
+
+impl ReportYaml {
+    pub fn new(
+        path: &Path,
+        report_output: ReportOutput,
+        report_suffix: Option<ReportSuffix>,
+    ) -> Result<Self, SimpleError> {
+        let output: Box<dyn Write> = match report_output {
+            ReportOutput::ToFile => create_report_file(path),
+            ReportOutput::ToStdout => stdout_writer(),
+        };
+        Ok(ReportYaml {
+            f: output,
+            report_output,
+            report_suffix,
+            values: RefCell::new(Vec::new()),
+            error: WriteError::default(),
+        })
+    }
+
+    pub fn write_values(&mut self) -> io::Result<()> {
+        let mut record = serde_yaml::Mapping::new();
+        if self.report_output == ReportOutput::ToStdout {
+            let report_suffix = self.report_suffix.as_ref().map_or("", |s| s.name());
+            record.insert("report_suffix".into(), report_suffix.into());
+        }
+        for (field, value) in self.values.borrow_mut().drain(..) {
+            record.insert(field.into(), value);
+        }
+        // serialized as a one element sequence, so records simply append to the report
+        let out = serde_yaml::to_string(&[record])
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        self.f.write_all(out.as_bytes())?;
+        flush_record(self.f.as_mut(), self.report_output)
+    }
+}
+
+impl Report for ReportYaml {
+    fn footer(&mut self) {
+        self.create_new_row();
+        self.error.check(self.f.flush());
+    }
+
+    fn create_new_row(&mut self) {
+        if self.is_some_val_in_record() {
+            let result = self.write_values();
+            self.error.check(result);
+        } else {
+            // a record of empty strings only is left out, as in json and csv
+            self.values.borrow_mut().clear();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.error.check(self.f.flush());
+    }
+
+    // serde_yaml quotes strings that would otherwise read back as another type (true, 123, !tag)
+    fn insert_str_val(&self, f: &str, s: String) {
+        self.values.borrow_mut().push((f.into(), s.into()));
+    }
+
+    fn insert_int_val(&self, f: &str, n: u64) {
+        self.values.borrow_mut().push((f.into(), n.into()));
+    }
+
+    fn insert_i64_val(&self, f: &str, n: i64) {
+        self.values.borrow_mut().push((f.into(), n.into()));
+    }
+
+    fn insert_bool_val(&self, f: &str, b: bool) {
+        self.values.borrow_mut().push((f.into(), b.into()));
+    }
+
+    fn insert_float_val(&self, f: &str, v: f64) {
+        self.values.borrow_mut().push((f.into(), v.into()));
+    }
+
+    fn write_error(&self) -> Option<io::ErrorKind> {
+        self.error.get()
+    }
+
+    fn is_some_val_in_record(&self) -> bool {
+        self.values.borrow().iter().any(|v| v.1 != "")
+    }
+}
+
+impl Drop for ReportYaml {
+    fn drop(&mut self) {
+        self.footer();
+    }
+}
+
+// report html
+const HTML_STYLE: &str = "table{border-collapse:collapse;font:13px sans-serif}\
+th,td{border:1px solid #ccc;padding:2px 6px;vertical-align:top}\
+th{background:#eee;cursor:pointer;position:sticky;top:0}";
+
+// Sorts the rows by the clicked column, numbers numerically, clicking again reverses the order.
+const HTML_SORT_SCRIPT: &str = r#"document.querySelectorAll('th').forEach((th, i) => th.addEventListener('click', () => {
+  const tbody = th.closest('table').tBodies[0];
+  const asc = th.dataset.order !== 'asc';
+  th.parentNode.querySelectorAll('th').forEach(h => delete h.dataset.order);
+  th.dataset.order = asc ? 'asc' : 'desc';
+  const key = r => r.cells[i] ? r.cells[i].textContent : '';
+  const rows = Array.from(tbody.rows).sort((a, b) => {
+    const x = key(a), y = key(b);
+    const n = x !== '' && y !== '' && !isNaN(x) && !isNaN(y) ? x - y : x.localeCompare(y);
+    return asc ? n : -n;
+  });
+  rows.forEach(r => tbody.appendChild(r));
+}));"#;
+
+pub struct ReportHtml {
+    f: Box<dyn Write + 'static>,
+    report_output: ReportOutput,
+    report_suffix: Option<ReportSuffix>,
+    started: bool, // document head and table header are written
+    finished: bool,
+    values: RefCell<Vec<(String /*field*/, String /*value*/)>>,
+    error: WriteError, // the first failed write
+}
+
+impl ReportHtml {
+    pub fn new(
+        path: &Path,
+        report_output: ReportOutput,
+        report_suffix: Option<ReportSuffix>,
+    ) -> Result<Self, SimpleError> {
+        let output: Box<dyn Write> = match report_output {
+            ReportOutput::ToFile => create_report_file(path),
+            ReportOutput::ToStdout => stdout_writer(),
+        };
+        Ok(ReportHtml {
+            f: output,
+            report_output,
+            report_suffix,
+            started: false,
+            finished: false,
+            values: RefCell::new(Vec::new()),
+            error: WriteError::default(),
+        })
+    }
+
+    // the columns are the fields known when the first record is written, as in csv
+    fn start(&mut self, out: &mut String) {
+        if !self.started {
+            let title = self.report_suffix.as_ref().map_or("", |s| s.name());
+            out.push_str(&format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+                 <style>{HTML_STYLE}</style>\n</head>\n<body>\n<table>\n<thead><tr>"
+            ));
+            for (field, _) in self.values.borrow().iter() {
+                out.push_str(&format!("<th>{}</th>", xml_escape(field)));
+            }
+            out.push_str("</tr></thead>\n<tbody>\n");
+            self.started = true;
+        }
+    }
+
+    pub fn write_values(&mut self) -> io::Result<()> {
+        let mut out = String::new();
+        self.start(&mut out);
+        out.push_str("<tr>");
+        for v in self.values.borrow_mut().iter_mut() {
+            out.push_str(&format!("<td>{}</td>", std::mem::take(&mut v.1)));
+        }
+        out.push_str("</tr>\n");
+        self.f.write_all(out.as_bytes())?;
+        flush_record(self.f.as_mut(), self.report_output)
+    }
+
+    pub fn update_field_with_value(&self, f: &str, v: String) {
+        let mut values = self.values.borrow_mut();
+        if let Some(found) = values.iter_mut().find(|i| i.0 == f) {
+            found.1 = v;
+        } else {
+            values.push((f.into(), v));
+        }
+    }
+}
+
+impl Report for ReportHtml {
+    fn footer(&mut self) {
+        self.create_new_row();
+        // a report without records doesn't create its file for the footer alone
+        if self.started && !self.finished {
+            let mut out = String::new();
+            out.push_str(&format!(
+                "</tbody>\n</table>\n<script>\n{HTML_SORT_SCRIPT}\n</script>\n</body>\n</html>\n"
+            ));
+            self.error.check(self.f.write_all(out.as_bytes()));
+            self.finished = true;
+        }
+        self.error.check(self.f.flush());
+    }
+
+    fn create_new_row(&mut self) {
+        if self.is_some_val_in_record() {
+            let result = self.write_values();
+            self.error.check(result);
+        }
+    }
+
+    fn flush(&mut self) {
+        self.error.check(self.f.flush());
+    }
+
+    fn insert_str_val(&self, f: &str, s: String) {
+        self.update_field_with_value(f, xml_escape(&s));
+    }
+
+    fn insert_int_val(&self, f: &str, n: u64) {
+        self.update_field_with_value(f, n.to_string());
+    }
+
+    fn set_field(&self, f: &str) {
+        self.update_field_with_value(f, "".to_string());
+    }
+
+    fn write_error(&self) -> Option<io::ErrorKind> {
+        self.error.get()
+    }
+
+    fn is_some_val_in_record(&self) -> bool {
+        self.values.borrow().iter().any(|i| !i.1.is_empty())
+    }
+}
+
+impl Drop for ReportHtml {
+    fn drop(&mut self) {
+        self.footer();
+    }
+}
+
+// report markdown
+pub struct ReportMarkdown {
+    f: Box<dyn Write + 'static>,
+    report_output: ReportOutput,
+    report_suffix: Option<ReportSuffix>,
+    first_record: Cell<bool>,
+    values: RefCell<Vec<(String /*field*/, String /*value*/)>>,
+    error: WriteError, // the first failed write
+}
+
+impl ReportMarkdown {
+    pub fn new(
+        path: &Path,
+        report_output: ReportOutput,
+        report_suffix: Option<ReportSuffix>,
+    ) -> Result<Self, SimpleError> {
+        let output: Box<dyn Write> = match report_output {
+            ReportOutput::ToFile => create_report_file(path),
+            ReportOutput::ToStdout => stdout_writer(),
+        };
+        Ok(ReportMarkdown {
+            f: output,
+            report_output,
+            report_suffix,
+            first_record: Cell::new(true),
+            values: RefCell::new(Vec::new()),
+            error: WriteError::default(),
+        })
+    }
+
+    // A pipe would end the cell and a line break the row; the rendered cell keeps the break.
+    fn escape(s: &str) -> String {
+        s.replace('|', "\\|")
+            .replace("\r\n", "<br>")
+            .replace(['\n', '\r'], "<br>")
+    }
+
+    fn row(cells: &[String]) -> String {
+        format!("| {} |\n", cells.join(" | "))
+    }
+
+    // the columns are the fields known when the first record is written, as in csv
+    pub fn write_header(&mut self) -> io::Result<()> {
+        let mut fields: Vec<String> = Vec::new();
+        if self.report_output == ReportOutput::ToStdout {
+            fields.push("ReportSuffix".to_string());
+        }
+        fields.extend(
+            self.values
+                .borrow()
+                .iter()
+                .map(|v| ReportMarkdown::escape(&v.0)),
+        );
+        let mut out = String::new();
+        if self.report_output == ReportOutput::ToStdout {
+            out.push('\n');
+        }
+        out.push_str(&ReportMarkdown::row(&fields));
+        out.push_str(&ReportMarkdown::row(&vec!["---".to_string(); fields.len()]));
+        self.f.write_all(out.as_bytes())
+    }
+
+    pub fn write_values(&mut self) -> io::Result<()> {
+        let mut row: Vec<String> = Vec::new();
+        if self.report_output == ReportOutput::ToStdout {
+            row.push(self.report_suffix.as_ref().unwrap().name().to_string());
+        }
+        // the record is consumed even if writing fails, so it can't leak into the next one
+        row.extend(
+            self.values
+                .borrow_mut()
+                .iter_mut()
+                .map(|v| std::mem::take(&mut v.1)),
+        );
+        self.f.write_all(ReportMarkdown::row(&row).as_bytes())?;
+        flush_record(self.f.as_mut(), self.report_output)
+    }
+
+    pub fn update_field_with_value(&self, f: &str, v: String) {
+        let mut values = self.values.borrow_mut();
+        if let Some(found) = values.iter_mut().find(|i| i.0 == f) {
+            found.1 = v;
+        } else {
+            values.push((f.into(), v));
+        }
+    }
+}
+
+impl Report for ReportMarkdown {
+    fn footer(&mut self) {
+        self.create_new_row();
+        self.error.check(self.f.flush());
+    }
+
+    fn create_new_row(&mut self) {
+        if self.is_some_val_in_record() {
+            if self.first_record.get() {
+                let result = self.write_header();
+                self.error.check(result);
+                self.first_record.set(false);
+            }
+            let result = self.write_values();
+            self.error.check(result);
+        }
+    }
+
+    fn flush(&mut self) {
+        self.error.check(self.f.flush());
+    }
+
+    fn insert_str_val(&self, f: &str, s: String) {
+        self.update_field_with_value(f, ReportMarkdown::escape(&s));
+    }
+
+    fn insert_int_val(&self, f: &str, n: u64) {
+        self.update_field_with_value(f, n.to_string());
+    }
+
+    fn set_field(&self, f: &str) {
+        self.update_field_with_value(f, "".to_string());
+    }
+
+    fn write_error(&self) -> Option<io::ErrorKind> {
+        self.error.get()
+    }
+
+    fn is_some_val_in_record(&self) -> bool {
+        self.values.borrow().iter().any(|i| !i.1.is_empty())
+    }
+}
+
+impl Drop for ReportMarkdown {
+    fn drop(&mut self) {
+        self.footer();
+    }
+}
+
+// report sqlite
+const SQLITE_ROWS_PER_TRANSACTION: usize = 1000;
+
+// The connection to a sqlite file, shared by all reports written into it. sqlite allows one
+// writer at a time, so reports with connections of their own would lock each other out.
+pub struct SqliteDb {
+    conn: sqlite::Connection,
+    pending: usize, // rows inserted by the open transaction
+}
+
+impl SqliteDb {
+    pub fn open(path: &Path) -> Result<Arc<Mutex<Self>>, SimpleError> {
+        let mut conn =
+            sqlite::Connection::open(path).map_err(|e| SimpleError::new(format!("{e}")))?;
+        // another process may be writing the file
+        conn.set_busy_timeout(60_000)
+            .map_err(|e| SimpleError::new(format!("{e}")))?;
+        Ok(Arc::new(Mutex::new(SqliteDb { conn, pending: 0 })))
+    }
+
+    fn commit(&mut self) -> Result<(), SimpleError> {
+        if self.pending > 0 {
+            self.conn
+                .execute("COMMIT")
+                .map_err(|e| SimpleError::new(format!("{e}")))?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+}
+
+pub struct ReportSqlite {
+    db: Arc<Mutex<SqliteDb>>,
+    table: String,
+    columns: Vec<String>,
+    values: RefCell<Vec<(String /*column*/, sqlite::Value)>>,
+}
+
+impl ReportSqlite {
+    pub fn new(path: &Path, table: &str) -> Result<Self, SimpleError> {
+        ReportSqlite::with_db(SqliteDb::open(path)?, table)
+    }
+
+    pub fn with_db(db: Arc<Mutex<SqliteDb>>, table: &str) -> Result<Self, SimpleError> {
+        db.lock()
+            .unwrap()
+            .conn
+            .execute(format!(
+                "CREATE TABLE IF NOT EXISTS {} (RowId INTEGER PRIMARY KEY)",
+                ReportSqlite::quote(table)
+            ))
+            .map_err(|e| SimpleError::new(format!("{e}")))?;
+        let mut rep = ReportSqlite {
+            db,
+            table: table.to_string(),
+            columns: Vec::new(),
+            values: RefCell::new(Vec::new()),
+        };
+        rep.columns = rep.table_columns()?;
+        Ok(rep)
+    }
+
+    fn quote(ident: &str) -> String {
+        format!("\"{}\"", ident.replace('"', "\"\""))
+    }
+
+    fn table_columns(&self) -> Result<Vec<String>, SimpleError> {
+        let q = format!("PRAGMA table_info({})", ReportSqlite::quote(&self.table));
+        let db = self.db.lock().unwrap();
+        let mut s = db
+            .conn
+            .prepare(q)
+            .map_err(|e| SimpleError::new(format!("{e}")))?;
+        let mut columns = Vec::new();
+        while let Ok(sqlite::State::Row) = s.next() {
+            columns.push(
+                s.read::<String, _>("name")
+                    .map_err(|e| SimpleError::new(format!("{e}")))?,
+            );
+        }
+        Ok(columns)
+    }
+
+    // columns can show up in the middle of a report, or be added by a report of another database
+    fn add_column(&mut self, conn: &sqlite::Connection, column: &str) -> Result<(), SimpleError> {
+        let q = format!(
+            "ALTER TABLE {} ADD COLUMN {}",
+            ReportSqlite::quote(&self.table),
+            ReportSqlite::quote(column)
+        );
+        match conn.execute(q) {
+            Ok(()) => {}
+            Err(e) if e.to_string().contains("duplicate column") => {}
+            Err(e) => return Err(SimpleError::new(format!("{e}"))),
+        }
+        self.columns.push(column.to_string());
+        Ok(())
+    }
+
+    pub fn write_values(&mut self) -> Result<(), SimpleError> {
+        let values = std::mem::take(&mut *self.values.borrow_mut());
+        let db = self.db.clone();
+        let mut db = db.lock().unwrap();
+        // the transaction is shared with the other reports of the connection
+        if db.pending == 0 {
+            db.conn
+                .execute("BEGIN IMMEDIATE")
+                .map_err(|e| SimpleError::new(format!("{e}")))?;
+        }
+        for (column, _) in &values {
+            if !self.columns.iter().any(|c| c.eq_ignore_ascii_case(column)) {
+                self.add_column(&db.conn, column)?;
+            }
+        }
+        let columns: Vec<String> = values.iter().map(|v| ReportSqlite::quote(&v.0)).collect();
+        let q = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            ReportSqlite::quote(&self.table),
+            columns.join(","),
+            vec!["?"; columns.len()].join(",")
+        );
+        {
+            let mut s = db
+                .conn
+                .prepare(q)
+                .map_err(|e| SimpleError::new(format!("{e}")))?;
+            for (i, (_, value)) in values.iter().enumerate() {
+                s.bind((i + 1, value))
+                    .map_err(|e| SimpleError::new(format!("{e}")))?;
+            }
+            s.next().map_err(|e| SimpleError::new(format!("{e}")))?;
+        }
+        db.pending += 1;
+        if db.pending >= SQLITE_ROWS_PER_TRANSACTION {
+            db.commit()?;
+        }
+        Ok(())
+    }
+
+    pub fn update_field_with_value(&self, f: &str, v: sqlite::Value) {
+        let mut values = self.values.borrow_mut();
+        if let Some(found) = values.iter_mut().find(|i| i.0 == f) {
+            found.1 = v;
+        } else {
+            values.push((f.into(), v));
+        }
+    }
+}
+
+impl Report for ReportSqlite {
+    // Commits on every call, footer may come after each record. The rows of the other
+    // reports sharing the connection are committed as well.
+    fn footer(&mut self) {
+        self.create_new_row();
+        if let Err(e) = self.db.lock().unwrap().commit() {
+            panic!("Failed to write report: {e}");
+        }
+    }
+
+    fn create_new_row(&mut self) {
+        if self.is_some_val_in_record() {
+            if let Err(e) = self.write_values() {
+                panic!("Failed to write report: {e}");
+            }
+        }
+    }
+
+    fn insert_str_val(&self, f: &str, s: String) {
+        self.update_field_with_value(f, sqlite::Value::String(s));
+    }
+
+    fn insert_int_val(&self, f: &str, n: u64) {
+        // sqlite integers are signed
+        let value = i64::try_from(n).map_or(sqlite::Value::String(n.to_string()), |n| {
+            sqlite::Value::Integer(n)
+        });
+        self.update_field_with_value(f, value);
+    }
+
+    fn insert_i64_val(&self, f: &str, n: i64) {
+        self.update_field_with_value(f, sqlite::Value::Integer(n));
+    }
+
+    fn insert_bool_val(&self, f: &str, b: bool) {
+        self.update_field_with_value(f, sqlite::Value::Integer(b as i64));
+    }
+
+    fn insert_float_val(&self, f: &str, v: f64) {
+        if !v.is_nan() {
+            self.update_field_with_value(f, sqlite::Value::Float(v));
+        }
+    }
+
+    fn set_field(&self, f: &str) {
+        // NULL values only make sure the column exists
+        self.update_field_with_value(f, sqlite::Value::Null);
+    }
+
+    fn is_some_val_in_record(&self) -> bool {
+        self.values
+            .borrow()
+            .iter()
+            .any(|v| !matches!(v.1, sqlite::Value::Null))
+    }
+}
+
+impl Drop for ReportSqlite {
+    fn drop(&mut self) {
+        self.footer();
+    }
+}
+
+// report xlsx
+const XLSX_MAX_ROWS: u32 = 1_048_576;
+
+enum XlsxValue {
+    Empty, // only declares the column
+    Str(String),
+    Num(f64),
+    Bool(bool),
+}
+
+struct XlsxSheet {
+    report: String,
+    name: String,
+    part: usize, // a report longer than the row limit continues on "<name> 2", ...
+    columns: Vec<String>,
+    next_row: u32,
+}
+
+// The workbook is shared by all reports of the run and saved by ReportProducer::finish.
+pub struct XlsxBook {
+    workbook: Workbook,
+    sheets: Vec<XlsxSheet>,
+}
+
+impl XlsxBook {
+    fn new() -> Self {
+        XlsxBook {
+            workbook: Workbook::new(),
+            sheets: Vec::new(),
+        }
+    }
+
+    fn sheet_index(&mut self, report: &str) -> Result<usize, XlsxError> {
+        let last = self.sheets.iter().rposition(|s| s.report == report);
+        if let Some(i) = last {
+            if self.sheets[i].next_row < XLSX_MAX_ROWS {
+                return Ok(i);
+            }
+        }
+        let (part, columns) = match last {
+            Some(i) => (self.sheets[i].part + 1, self.sheets[i].columns.clone()),
+            None => (1, Vec::new()),
+        };
+        let base = report.replace('_', " ");
+        let name = if part == 1 {
+            base
+        } else {
+            format!("{base} {part}")
+        };
+        let worksheet = self.workbook.add_worksheet();
+        worksheet.set_name(&name)?;
+        for (col, column) in columns.iter().enumerate() {
+            worksheet.write_string(0, col as u16, column.as_str())?;
+        }
+        self.sheets.push(XlsxSheet {
+            report: report.to_string(),
+            name,
+            part,
+            columns,
+            next_row: 1,
+        });
+        Ok(self.sheets.len() - 1)
+    }
+
+    fn write_record(
+        &mut self,
+        report: &str,
+        values: &[(String, XlsxValue)],
+    ) -> Result<(), XlsxError> {
+        let i = self.sheet_index(report)?;
+        let sheet = &mut self.sheets[i];
+        let worksheet = self.workbook.worksheet_from_name(&sheet.name)?;
+        for (field, value) in values {
+            let col = match sheet.columns.iter().position(|c| c == field) {
+                Some(col) => col,
+                None => {
+                    sheet.columns.push(field.clone());
+                    let col = sheet.columns.len() - 1;
+                    worksheet.write_string(0, col as u16, field.as_str())?;
+                    col
+                }
+            } as u16;
+            match value {
+                XlsxValue::Empty => {}
+                XlsxValue::Str(s) => {
+                    worksheet.write_string(sheet.next_row, col, s.as_str())?;
+                }
+                XlsxValue::Num(n) => {
+                    worksheet.write_number(sheet.next_row, col, *n)?;
+                }
+                XlsxValue::Bool(b) => {
+                    worksheet.write_boolean(sheet.next_row, col, *b)?;
+                }
+            }
+        }
+        sheet.next_row += 1;
+        Ok(())
+    }
+}
+
+pub struct ReportXlsx {
+    book: Arc<Mutex<XlsxBook>>,
+    report: String,
+    values: RefCell<Vec<(String /*column*/, XlsxValue)>>,
+}
+
+impl ReportXlsx {
+    pub fn new(book: Arc<Mutex<XlsxBook>>, report: &str) -> Self {
+        ReportXlsx {
+            book,
+            report: report.to_string(),
+            values: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn update_field_with_value(&self, f: &str, v: XlsxValue) {
+        let mut values = self.values.borrow_mut();
+        if let Some(found) = values.iter_mut().find(|i| i.0 == f) {
+            found.1 = v;
+        } else {
+            values.push((f.into(), v));
+        }
+    }
+}
+
+impl Report for ReportXlsx {
+    fn footer(&mut self) {
+        self.create_new_row();
+    }
+
+    fn create_new_row(&mut self) {
+        if self.is_some_val_in_record() {
+            let values = std::mem::take(&mut *self.values.borrow_mut());
+            let mut book = self.book.lock().unwrap();
+            if let Err(e) = book.write_record(&self.report, &values) {
+                panic!("Failed to write report: {e}");
+            }
+        }
+    }
+
+    fn insert_str_val(&self, f: &str, s: String) {
+        self.update_field_with_value(f, XlsxValue::Str(s));
+    }
+
+    fn insert_int_val(&self, f: &str, n: u64) {
+        // excel numbers are doubles, keep larger values exact as text
+        let value = if n < (1 << f64::MANTISSA_DIGITS) {
+            XlsxValue::Num(n as f64)
+        } else {
+            XlsxValue::Str(n.to_string())
+        };
+        self.update_field_with_value(f, value);
+    }
+
+    fn insert_i64_val(&self, f: &str, n: i64) {
+        let value = if n.unsigned_abs() < (1 << f64::MANTISSA_DIGITS) {
+            XlsxValue::Num(n as f64)
+        } else {
+            XlsxValue::Str(n.to_string())
+        };
+        self.update_field_with_value(f, value);
+    }
+
+    fn insert_bool_val(&self, f: &str, b: bool) {
+        self.update_field_with_value(f, XlsxValue::Bool(b));
+    }
+
+    fn insert_float_val(&self, f: &str, v: f64) {
+        // excel has no NaN or infinity
+        let value = if v.is_finite() {
+            XlsxValue::Num(v)
+        } else {
+            XlsxValue::Str(v.to_string())
+        };
+        self.update_field_with_value(f, value);
+    }
+
+    fn set_field(&self, f: &str) {
+        self.update_field_with_value(f, XlsxValue::Empty);
+    }
+
+    fn is_some_val_in_record(&self) -> bool {
+        self.values
+            .borrow()
+            .iter()
+            .any(|v| !matches!(v.1, XlsxValue::Empty))
+    }
+}
+
+impl Drop for ReportXlsx {
+    fn drop(&mut self) {
+        self.footer();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::{
+        Report, ReportArchive, ReportCsv, ReportFormat, ReportHtml, ReportJson, ReportKind,
+        ReportMarkdown, ReportOutput, ReportProducer, ReportSqlite, ReportSuffix, ReportXlsx,
+        ReportXml, ReportYaml, XlsxBook,
+    };
+    use chrono::{DateTime, NaiveDate, Utc};
+    use ese_parser_lib::parser::jet::DbState;
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    // keeps what a report writes readable after the report is dropped
+    #[derive(Clone, Default)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedBuf {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_report_write_error() {
+        use crate::record::{RecordFilter, RecordOptions};
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::{Arc, Mutex};
+
+        let run_write_error = Arc::new(Mutex::new(None));
+        {
+            let r = ReportJson::with_writer(
+                Box::new(ClosedPipe),
+                ReportOutput::ToStdout,
+                Some(ReportSuffix::FileReport),
+                false,
+            );
+            let mut rep = RecordFilter::new(
+                Box::new(r),
+                Arc::new(RecordOptions::default()),
+                "File_Report",
+                Arc::new(AtomicUsize::new(0)),
+            )
+            .with_run_write_error(run_write_error.clone());
+            assert!(!rep.is_full());
+            rep.insert_int_val("int_field", 0);
+            rep.create_new_row();
+            assert_eq!(rep.write_error(), Some(std::io::ErrorKind::BrokenPipe));
+            // the database needn't be read any further
+            assert!(rep.is_full());
+        }
+        assert_eq!(
+            *run_write_error.lock().unwrap(),
+            Some(std::io::ErrorKind::BrokenPipe)
+        );
+    }
+
+    #[test]
+    pub fn test_report_with_writer() {
+        let buf = SharedBuf::default();
+        {
+            let mut r =
+                ReportJson::with_writer(Box::new(buf.clone()), ReportOutput::ToFile, None, false);
+            r.insert_int_val("int_field", 0);
+            r.create_new_row();
+            r.insert_str_val("str_field", "string1".into());
+        }
+        assert_eq!(
+            buf.contents(),
+            "{\"int_field\":0}\n{\"str_field\":\"string1\"}\n"
+        );
+
+        let buf = SharedBuf::default();
+        {
+            let r = ReportCsv::with_writer(
+                Box::new(buf.clone()),
+                ReportOutput::ToStdout,
+                Some(ReportSuffix::FileReport),
+                ',',
+                false,
+            );
+            r.insert_int_val("int_field", 0);
+        }
+        assert_eq!(
+            buf.contents(),
+            "\nReportSuffix,int_field\n\"file_report\",0"
+        );
+    }
+
+    #[test]
+    pub fn test_report_json_envelope() {
+        let generated = DateTime::<Utc>::from_utc(
+            NaiveDate::from_ymd_opt(2023, 3, 7)
+                .unwrap()
+                .and_hms_opt(1, 52, 44)
+                .unwrap(),
+            Utc,
+        );
+        let buf = SharedBuf::default();
+        {
+            let mut r =
+                ReportJson::with_writer(Box::new(buf.clone()), ReportOutput::ToFile, None, false)
+                    .with_envelope("host", Some(Path::new("Windows.db")), generated);
+            r.insert_int_val("int_field", 0);
+            r.create_new_row();
+            r.insert_int_val("int_field", 1);
+        }
+        let data = buf.contents();
+        let report: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(report["tool"], "sidr");
+        assert_eq!(report["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(report["host"], "host");
+        assert_eq!(report["source_db"], "Windows.db");
+        assert_eq!(report["generated"], "2023-03-07T01:52:44Z");
+        assert_eq!(
+            report["records"],
+            serde_json::json!([{"int_field": 0}, {"int_field": 1}])
+        );
+
+        let buf = SharedBuf::default();
+        {
+            let r =
+                ReportJson::with_writer(Box::new(buf.clone()), ReportOutput::ToFile, None, true)
+                    .with_envelope("All_Hosts", None, generated);
+            r.insert_int_val("int_field", 0);
+        }
+        let report: serde_json::Value = serde_json::from_str(&buf.contents()).unwrap();
+        assert_eq!(report["source_db"], serde_json::Value::Null);
+        assert_eq!(report["records"], serde_json::json!([{"int_field": 0}]));
+
+        // no records - nothing is written, not even the envelope
+        let buf = SharedBuf::default();
+        {
+            let _r =
+                ReportJson::with_writer(Box::new(buf.clone()), ReportOutput::ToFile, None, true)
+                    .with_envelope("All_Hosts", None, generated);
+        }
+        assert_eq!(buf.contents(), "");
+    }
+
+    #[test]
+    pub fn test_report_csv_comments() {
+        let generated = DateTime::<Utc>::from_utc(
+            NaiveDate::from_ymd_opt(2023, 3, 7)
+                .unwrap()
+                .and_hms_opt(1, 52, 44)
+                .unwrap(),
+            Utc,
+        );
+        let buf = SharedBuf::default();
+        {
+            let r = ReportCsv::with_writer(
+                Box::new(buf.clone()),
+                ReportOutput::ToFile,
+                None,
+                ',',
+                true,
+            )
+            .with_comments("host", Some(Path::new("Windows.db")), generated);
+            r.insert_int_val("int_field", 0);
+        }
+        assert_eq!(
+            buf.contents(),
+            format!(
+                "\u{FEFF}# tool=sidr version={} host=host source=Windows.db generated=2023-03-07T01:52:44Z\nint_field\n0",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_report_csv() {
+        let p = Path::new("test.csv");
+        let report_type = ReportOutput::ToFile;
+        let report_suffix = None;
+        {
+            let mut r = ReportCsv::new(p, report_type, report_suffix, ',', false).unwrap();
+            r.set_field("int_field");
+            r.set_field("str_field");
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "string0".into());
+            for i in 1..10 {
+                r.create_new_row();
+                if i % 2 == 0 {
+                    r.insert_str_val("str_field", format!("string{}", i));
+                } else {
+                    r.insert_int_val("int_field", i);
+                }
+            }
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        let expected = r#"int_field,str_field
+0,"string0"
+1,
+,"string2"
+3,
+,"string4"
+5,
+,"string6"
+7,
+,"string8"
+9,"#;
+        assert_eq!(data, expected);
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_csv_embedded_newline() {
+        let p = Path::new("test_newline.csv");
+        {
+            let mut r = ReportCsv::new(p, ReportOutput::ToFile, None, ',', false).unwrap();
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "line1\r\nline2, \"quoted\"".into());
+            r.create_new_row();
+            r.insert_int_val("int_field", 1);
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        let expected = r#"int_field,str_field
+0,"line1\r\nline2, ""quoted"""
+1,"#;
+        assert_eq!(data, expected);
+        assert_eq!(data.lines().count(), 3);
+        std::fs::remove_file(p).unwrap();
+
+        {
+            let mut r = ReportCsv::new(p, ReportOutput::ToFile, None, ',', false)
+                .unwrap()
+                .with_multiline(true);
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "line1\r\nline2".into());
+            r.create_new_row();
+            r.insert_int_val("int_field", 1);
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        let expected = "int_field,str_field\n0,\"line1\r\nline2\"\n1,";
+        assert_eq!(data, expected);
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_csv_delimiter() {
+        let p = Path::new("test_delimiter.csv");
+        {
+            let mut r = ReportCsv::new(p, ReportOutput::ToFile, None, ';', false).unwrap();
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "a;b".into());
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        assert_eq!(data, "int_field;str_field\n0;\"a;b\"");
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_csv_bom() {
+        let p = Path::new("test_bom.csv");
+        {
+            let r = ReportCsv::new(p, ReportOutput::ToFile, None, ',', true).unwrap();
+            r.insert_int_val("int_field", 0);
+        }
+        let data = std::fs::read(p).unwrap();
+        assert_eq!(data, b"\xEF\xBB\xBFint_field\n0");
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_csv_always_header() {
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_header").unwrap();
+        let p = dir.path().join("header.csv");
+        {
+            let r = ReportCsv::new(&p, ReportOutput::ToFile, None, ',', false)
+                .unwrap()
+                .with_always_header(true);
+            r.set_field("int_field");
+            r.set_field("str_field");
+        }
+        assert_eq!(std::fs::read_to_string(&p).unwrap(), "int_field,str_field");
+    }
+
+    #[test]
+    pub fn test_report_tsv() {
+        let p = Path::new("test.tsv");
+        {
+            let mut r = ReportCsv::new(p, ReportOutput::ToFile, None, '\t', false).unwrap();
+            r.set_field("int_field");
+            r.set_field("str_field");
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "C:\\a\tb".into());
+            r.create_new_row();
+            r.insert_str_val("str_field", "line1\nline2".into());
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        assert_eq!(data, "int_field\tstr_field\n0\tC:\\a\\tb\n\tline1\\nline2");
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_gzip() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let p = Path::new("test.json.gz");
+        {
+            let r = ReportJson::new(p, ReportOutput::ToFile, None, false).unwrap();
+            r.insert_int_val("int_field", 0);
+        }
+        let mut data = String::new();
+        GzDecoder::new(std::fs::File::open(p).unwrap())
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "{\"int_field\":0}\n");
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_jsonl() {
+        let p = Path::new("test.json");
+        let report_type = ReportOutput::ToFile;
+        let report_suffix = Some(ReportSuffix::FileReport);
+        {
+            let mut r = ReportJson::new(p, report_type, report_suffix, false).unwrap();
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "string0_with_escapes_here1\"here2\\".into());
+            for i in 1..10 {
+                r.create_new_row();
+                if i % 2 == 0 {
+                    r.insert_str_val("str_field", format!("string{}", i));
+                } else {
+                    r.insert_int_val("int_field", i);
+                }
+            }
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        let expected = r#"{"int_field":0,"str_field":"string0_with_escapes_here1\"here2\\"}
+{"int_field":1}
+{"str_field":"string2"}
+{"int_field":3}
+{"str_field":"string4"}
+{"int_field":5}
+{"str_field":"string6"}
+{"int_field":7}
+{"str_field":"string8"}
+{"int_field":9}
+"#;
+        assert_eq!(data, expected);
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_typed_values() {
+        let p = Path::new("test_typed_values.json");
+        {
+            let r = ReportJson::new(p, ReportOutput::ToFile, None, false).unwrap();
+            r.insert_bool_val("bool_field", true);
+            r.insert_i64_val("i64_field", -42);
+            r.insert_float_val("float_field", 1.5);
+            r.insert_float_val("nan_field", f64::NAN);
+            r.insert_float_val("inf_field", f64::INFINITY);
+            r.insert_datetime_val(
+                "datetime_field",
+                DateTime::<Utc>::from_utc(
+                    NaiveDate::from_ymd_opt(2023, 3, 7)
+                        .unwrap()
+                        .and_hms_milli_opt(1, 52, 44, 123)
+                        .unwrap(),
+                    Utc,
+                ),
+            );
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        assert_eq!(
+            data,
+            "{\"bool_field\":true,\"i64_field\":-42,\"float_field\":1.5,\"nan_field\":null,\"inf_field\":null,\"datetime_field\":\"2023-03-07T01:52:44.1230000Z\"}\n"
+        );
+        std::fs::remove_file(p).unwrap();
+
+        let p = Path::new("test_typed_values.csv");
+        {
+            let r = ReportCsv::new(p, ReportOutput::ToFile, None, ',', false).unwrap();
+            r.insert_bool_val("bool_field", false);
+            r.insert_i64_val("i64_field", i64::MIN);
+            r.insert_float_val("float_field", -0.25);
+            r.set_field("nan_field");
+            r.insert_float_val("nan_field", f64::NAN);
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        assert_eq!(
+            data,
+            "bool_field,i64_field,float_field,nan_field\nfalse,-9223372036854775808,-0.25,"
+        );
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_es_bulk() {
+        let p = Path::new("test.ndjson");
+        {
+            let mut r = ReportJson::new(p, ReportOutput::ToFile, None, false)
+                .unwrap()
+                .with_bulk_action(None);
+            r.insert_int_val("int_field", 0);
+            r.create_new_row();
+            r.insert_int_val("int_field", 1);
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        assert_eq!(
+            data,
+            "{\"index\":{}}\n{\"int_field\":0}\n{\"index\":{}}\n{\"int_field\":1}\n"
+        );
+        std::fs::remove_file(p).unwrap();
+
+        {
+            let r = ReportJson::new(p, ReportOutput::ToFile, None, false)
+                .unwrap()
+                .with_bulk_action(Some("sidr"));
+            r.insert_int_val("int_field", 0);
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        assert_eq!(
+            data,
+            "{\"index\":{\"_index\":\"sidr\"}}\n{\"int_field\":0}\n"
+        );
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_json_array() {
+        let p = Path::new("test_array.json");
+        {
+            let mut r = ReportJson::new(p, ReportOutput::ToFile, None, true).unwrap();
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "string0".into());
+            r.create_new_row();
+            r.insert_int_val("int_field", 1);
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        let expected = r#"[
+{"int_field":0,"str_field":"string0"},
+{"int_field":1}
+]
+"#;
+        assert_eq!(data, expected);
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_no_records() {
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_empty").unwrap();
+        let json = dir.path().join("empty.json");
+        let csv = dir.path().join("empty.csv.gz");
+        let json_array = dir.path().join("empty_array.json");
+        let xml = dir.path().join("empty.xml");
+        let yaml = dir.path().join("empty.yaml");
+        let html = dir.path().join("empty.html");
+        {
+            let mut r = ReportJson::new(&json, ReportOutput::ToFile, None, false).unwrap();
+            r.create_new_row();
+            let _r = ReportCsv::new(&csv, ReportOutput::ToFile, None, ',', true).unwrap();
+            let _r = ReportJson::new(&json_array, ReportOutput::ToFile, None, true).unwrap();
+            let _r = ReportXml::new(&xml, ReportOutput::ToFile, None).unwrap();
+            let _r = ReportYaml::new(&yaml, ReportOutput::ToFile, None).unwrap();
+            let _r = ReportHtml::new(&html, ReportOutput::ToFile, None).unwrap();
+        }
+        for p in [json, csv, json_array, xml, yaml, html] {
+            assert!(!p.exists(), "{}", p.to_string_lossy());
+        }
+    }
+
+    #[test]
+    pub fn test_report_empty_record() {
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_empty_record").unwrap();
+        let json = dir.path().join("empty_record.json");
+        let csv = dir.path().join("empty_record.csv");
+        let xml = dir.path().join("empty_record.xml");
+        let yaml = dir.path().join("empty_record.yaml");
+        {
+            let mut reps: Vec<Box<dyn Report>> = vec![
+                Box::new(ReportJson::new(&json, ReportOutput::ToFile, None, false).unwrap()),
+                Box::new(ReportCsv::new(&csv, ReportOutput::ToFile, None, ',', false).unwrap()),
+                Box::new(ReportXml::new(&xml, ReportOutput::ToFile, None).unwrap()),
+                Box::new(ReportYaml::new(&yaml, ReportOutput::ToFile, None).unwrap()),
+            ];
+            for r in reps.iter_mut() {
+                r.insert_str_val("str_field", "".into());
+                assert!(!r.is_some_val_in_record());
+                r.create_new_row();
+                r.insert_str_val("str_field", "string1".into());
+                assert!(r.is_some_val_in_record());
+                r.create_new_row();
+            }
+        }
+        assert_eq!(
+            std::fs::read_to_string(&json).unwrap(),
+            "{\"str_field\":\"string1\"}\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&csv).unwrap(),
+            "str_field\n\"string1\""
+        );
+        let data = std::fs::read_to_string(&xml).unwrap();
+        assert_eq!(data.matches("<record>").count(), 1, "{data}");
+        let data = std::fs::read_to_string(&yaml).unwrap();
+        let records: Vec<serde_yaml::Mapping> = serde_yaml::from_str(&data).unwrap();
+        assert_eq!(records.len(), 1, "{data}");
+    }
+
+    #[test]
+    pub fn test_report_flush() {
+        let p = Path::new("test_flush.json");
+        {
+            let mut r = ReportJson::new(p, ReportOutput::ToFile, None, false).unwrap();
+            r.insert_int_val("int_field", 0);
+            r.create_new_row();
+            r.insert_int_val("int_field", 1);
+            r.flush();
+            assert_eq!(std::fs::read_to_string(p).unwrap(), "{\"int_field\":0}\n");
+        }
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_xml() {
+        let p = Path::new("test.xml");
+        {
+            let mut r =
+                ReportXml::new(p, ReportOutput::ToFile, Some(ReportSuffix::FileReport)).unwrap();
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "<a> & \"b\"".into());
+            r.create_new_row();
+            r.insert_int_val("1st field", 1);
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<report type="file_report">
+<record>
+  <int_field>0</int_field>
+  <str_field>&lt;a&gt; &amp; &quot;b&quot;</str_field>
+</record>
+<record>
+  <_1st_field>1</_1st_field>
+</record>
+</report>
+"#;
+        assert_eq!(data, expected);
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    pub fn test_report_html() {
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_html").unwrap();
+        let p = dir.path().join("test.html");
+        {
+            let mut r =
+                ReportHtml::new(&p, ReportOutput::ToFile, Some(ReportSuffix::FileReport)).unwrap();
+            r.set_field("int_field");
+            r.set_field("str_field");
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "<a> & \"b\"".into());
+            r.create_new_row();
+            r.insert_int_val("int_field", 1);
+        }
+        let data = std::fs::read_to_string(&p).unwrap();
+        assert!(data.starts_with("<!DOCTYPE html>\n"), "{data}");
+        assert!(data.contains("<title>file_report</title>"), "{data}");
+        assert!(
+            data.contains(
+                "<thead><tr><th>int_field</th><th>str_field</th></tr></thead>\n<tbody>\n\
+                 <tr><td>0</td><td>&lt;a&gt; &amp; &quot;b&quot;</td></tr>\n\
+                 <tr><td>1</td><td></td></tr>\n</tbody>"
+            ),
+            "{data}"
+        );
+        assert!(data.ends_with("</script>\n</body>\n</html>\n"), "{data}");
+    }
 
-// report csv
-pub struct ReportCsv {
-    f: Box<dyn Write + 'static>,
-    report_output: ReportOutput,
-    report_suffix: Option<ReportSuffix>,
-    first_record: Cell<bool>,
-    values: RefCell<Vec<(String /*field*/, String /*value*/)>>,
-}
+    #[test]
+    pub fn test_report_markdown() {
+        use tempdir::TempDir;
 
-impl ReportCsv {
-    pub fn new(
-        f: &Path,
-        report_output: ReportOutput,
-        report_suffix: Option<ReportSuffix>,
-    ) -> Result<Self, SimpleError> {
-        match report_output {
-            ReportOutput::ToFile => {
-                let output: Box<dyn Write> =
-                    Box::new(File::create(f).map_err(|e| SimpleError::new(format!("{e}")))?);
-                Ok(ReportCsv {
-                    f: output,
-                    report_output,
-                    report_suffix: None,
-                    first_record: Cell::new(true),
-                    values: RefCell::new(Vec::new()),
-                })
-            }
-            ReportOutput::ToStdout => Ok(ReportCsv {
-                f: Box::new(BufWriter::new(io::stdout())),
-                report_output,
-                report_suffix,
-                first_record: Cell::new(true),
-                values: RefCell::new(Vec::new()),
-            }),
+        let dir = TempDir::new("sidr_markdown").unwrap();
+        let p = dir.path().join("test.md");
+        {
+            let mut r = ReportMarkdown::new(&p, ReportOutput::ToFile, None).unwrap();
+            r.set_field("int_field");
+            r.set_field("str_field");
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "a|b\r\nc".into());
+            r.create_new_row();
+            r.insert_int_val("int_field", 1);
         }
+        let data = std::fs::read_to_string(&p).unwrap();
+        let expected = r#"| int_field | str_field |
+| --- | --- |
+| 0 | a\|b<br>c |
+| 1 |  |
+"#;
+        assert_eq!(data, expected);
     }
 
-    fn escape(s: String) -> String {
-        s.replace('\"', "\"\"")
-            .replace("\n", "\\n")
-            .replace("\r", "\\r")
+    #[test]
+    pub fn test_report_yaml() {
+        let p = Path::new("test.yaml");
+        {
+            let mut r = ReportYaml::new(p, ReportOutput::ToFile, None).unwrap();
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "string0".into());
+            r.create_new_row();
+            r.insert_str_val("bool_like", "true".into());
+            r.insert_str_val("int_like", "123".into());
+            r.insert_str_val("tag_like", "!tag".into());
+        }
+        let data = std::fs::read_to_string(p).unwrap();
+        let records: Vec<serde_yaml::Mapping> = serde_yaml::from_str(&data).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["int_field"], serde_yaml::Value::from(0));
+        assert_eq!(records[0]["str_field"], serde_yaml::Value::from("string0"));
+        assert_eq!(records[1]["bool_like"], serde_yaml::Value::from("true"));
+        assert_eq!(records[1]["int_like"], serde_yaml::Value::from("123"));
+        assert_eq!(records[1]["tag_like"], serde_yaml::Value::from("!tag"));
+        std::fs::remove_file(p).unwrap();
     }
 
-    pub fn write_header(&mut self) {
-        let handle = self.f.as_mut();
-        if self.report_output == ReportOutput::ToStdout {
-            handle.write_all(b"\nReportSuffix,").ok();
+    #[test]
+    pub fn test_report_sqlite() {
+        let p = Path::new("test.sqlite");
+        {
+            let mut r = ReportSqlite::new(p, "File_Report").unwrap();
+            r.set_field("int_field");
+            r.insert_int_val("int_field", 0);
+            r.insert_str_val("str_field", "string0".into());
+            r.create_new_row();
+            r.insert_int_val("int_field", 1);
+            r.insert_str_val("new_field", "string1".into());
         }
-        let values = self.values.borrow();
-        for i in 0..values.len() {
-            let v = &values[i];
-            if i == values.len() - 1 {
-                handle.write_all(v.0.as_bytes()).unwrap();
-            } else {
-                handle.write_all(format!("{},", v.0).as_bytes()).unwrap();
-            }
+        let c = sqlite::Connection::open(p).unwrap();
+        let mut s = c
+            .prepare("SELECT int_field, str_field, new_field FROM File_Report ORDER BY RowId")
+            .unwrap();
+        let mut rows = Vec::new();
+        while let Ok(sqlite::State::Row) = s.next() {
+            rows.push((
+                s.read::<i64, _>("int_field").unwrap(),
+                s.read::<Option<String>, _>("str_field").unwrap(),
+                s.read::<Option<String>, _>("new_field").unwrap(),
+            ));
         }
+        assert_eq!(
+            rows,
+            vec![
+                (0, Some("string0".to_string()), None),
+                (1, None, Some("string1".to_string()))
+            ]
+        );
+        drop(s);
+        drop(c);
+        std::fs::remove_file(p).unwrap();
     }
 
-    pub fn write_values(&mut self) {
-        let handle = self.f.as_mut();
-        handle.write_all(b"\n").unwrap();
+    #[test]
+    pub fn test_report_sqlite_shared() {
+        use tempdir::TempDir;
 
-        let mut values = self.values.borrow_mut();
-        let len = values.len();
-        if self.report_output == ReportOutput::ToStdout {
-            handle
-                .write_all(format!("{},", self.report_suffix.as_ref().unwrap()).as_bytes())
-                .ok();
-        }
-        for i in 0..len {
-            let v = values.index_mut(i);
-            let last = if i == len - 1 { "" } else { "," };
-            if v.1.is_empty() {
-                handle.write_all(last.to_string().as_bytes()).unwrap();
-            } else {
-                handle
-                    .write_all(format!("{}{}", v.1, last).as_bytes())
-                    .unwrap();
-                v.1.clear();
-            }
+        let dir = TempDir::new("sidr_sqlite_shared").unwrap();
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Sqlite, ReportOutput::ToFile).unwrap();
+        let dbpath = Path::new("Windows.edb");
+        let (paths, mut file_rep) = rep_producer
+            .new_report(dbpath, "host", "File_Report", None)
+            .unwrap();
+        let (_, mut act_rep) = rep_producer
+            .new_report(dbpath, "host", "Activity_History_Report", None)
+            .unwrap();
+        // the reports of a database are written by the same thread, record by record
+        for i in 0..3 {
+            file_rep.insert_int_val("WorkId", i);
+            file_rep.create_new_row();
+            act_rep.insert_int_val("WorkId", i);
+            act_rep.create_new_row();
+            act_rep.footer();
         }
-        handle.flush().unwrap();
+        act_rep.insert_int_val("WorkId", 3);
+        drop(file_rep);
+        drop(act_rep);
+        // rep_producer still holds the connection, the rows must be committed anyway
+        let c = sqlite::Connection::open(&paths[0]).unwrap();
+        let count = |table: &str| {
+            let mut s = c
+                .prepare(format!("SELECT COUNT(*) AS n FROM {table}"))
+                .unwrap();
+            assert_eq!(s.next().unwrap(), sqlite::State::Row);
+            s.read::<i64, _>("n").unwrap()
+        };
+        assert_eq!(count("File_Report"), 3);
+        assert_eq!(count("Activity_History_Report"), 4);
     }
 
-    pub fn update_field_with_value(&self, f: &str, v: String) {
-        let mut values = self.values.borrow_mut();
-        if let Some(found) = values.iter_mut().find(|i| i.0 == f) {
-            found.1 = v;
-        } else {
-            values.push((f.into(), v));
+    #[test]
+    pub fn test_report_xlsx() {
+        use std::sync::{Arc, Mutex};
+
+        let book = Arc::new(Mutex::new(XlsxBook::new()));
+        for i in 0..2 {
+            let mut r = ReportXlsx::new(book.clone(), "File_Report");
+            r.set_field("int_field");
+            r.insert_int_val("int_field", i);
+            r.create_new_row();
+            r.insert_str_val("str_field", format!("string{i}"));
+        }
+        {
+            let r = ReportXlsx::new(book.clone(), "Activity_History_Report");
+            r.insert_int_val("int_field", 0);
         }
+        let mut book = book.lock().unwrap();
+        let sheets: Vec<(&str, &[String], u32)> = book
+            .sheets
+            .iter()
+            .map(|s| (s.name.as_str(), s.columns.as_slice(), s.next_row))
+            .collect();
+        assert_eq!(
+            sheets,
+            vec![
+                (
+                    "File Report",
+                    &["int_field".to_string(), "str_field".to_string()][..],
+                    5
+                ),
+                ("Activity History Report", &["int_field".to_string()][..], 2)
+            ]
+        );
+        let p = Path::new("test.xlsx");
+        book.workbook.save(p).unwrap();
+        assert!(std::fs::metadata(p).unwrap().len() > 0);
+        std::fs::remove_file(p).unwrap();
     }
-}
 
-impl Report for ReportCsv {
-    fn footer(&mut self) {
-        self.create_new_row();
+    #[test]
+    fn test_manifest() {
+        use sha2::{Digest, Sha256};
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_manifest").unwrap();
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile).unwrap();
+        let (paths, r) = rep_producer
+            .new_report(Path::new(""), "host", "File_Report", None)
+            .unwrap();
+        let path = &paths[0];
+        r.insert_int_val("int_field", 0);
+        drop(r);
+        rep_producer.mark_partial(Path::new(""));
+        rep_producer.finish().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join("manifest.json")).unwrap())
+                .unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let entry = &manifest["reports"][name];
+        assert_eq!(entry["size"], data.len());
+        assert_eq!(
+            entry["sha256"],
+            format!("{:x}", Sha256::digest(&data)).as_str()
+        );
+        assert_eq!(entry["partial"], true);
+        assert_eq!(manifest["version"], env!("CARGO_PKG_VERSION"));
+        let summary = rep_producer.summary();
+        assert_eq!((summary[0].records, summary[0].partial), (1, true));
     }
 
-    fn create_new_row(&mut self) {
-        // at least 1 value was recorded?
-        if self.is_some_val_in_record() {
-            if self.first_record.get() {
-                self.write_header();
-                self.first_record.set(false);
-            }
-            self.write_values();
-        }
+    #[test]
+    fn test_resolve_path() {
+        use crate::report::ClobberPolicy;
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_clobber").unwrap();
+        let path = dir
+            .path()
+            .join("host_File_Report_20230307_015244.1.json.gz");
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile).unwrap();
+        assert_eq!(
+            rep_producer.resolve_path(path.clone(), "json.gz").unwrap(),
+            path
+        );
+        // not created yet, but already taken by a report of the run
+        assert!(rep_producer.resolve_path(path.clone(), "json.gz").is_err());
+        std::fs::write(&path, b"").unwrap();
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile).unwrap();
+        assert!(rep_producer.resolve_path(path.clone(), "json.gz").is_err());
+        let rep_producer = rep_producer.with_clobber(ClobberPolicy::Rename);
+        let renamed = dir
+            .path()
+            .join("host_File_Report_20230307_015244.1_1.json.gz");
+        assert_eq!(
+            rep_producer.resolve_path(path.clone(), "json.gz").unwrap(),
+            renamed
+        );
+        let renamed = dir
+            .path()
+            .join("host_File_Report_20230307_015244.1_2.json.gz");
+        assert_eq!(
+            rep_producer.resolve_path(path.clone(), "json.gz").unwrap(),
+            renamed
+        );
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile)
+                .unwrap()
+                .with_clobber(ClobberPolicy::Overwrite);
+        assert_eq!(
+            rep_producer.resolve_path(path.clone(), "json.gz").unwrap(),
+            path
+        );
     }
 
-    fn insert_str_val(&self, f: &str, s: String) {
-        self.update_field_with_value(f, format!("\"{}\"", ReportCsv::escape(s)));
+    #[test]
+    fn test_filename_template() {
+        use crate::report::FilenameTemplate;
+
+        let template: FilenameTemplate = "CASE42_{host}_{report}_{date}T{time}.{ext}"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            template.render(&[
+                ("host", "DESKTOP-12345"),
+                ("report", "File_Report"),
+                ("date", "20230307"),
+                ("time", "015244"),
+                ("ext", "json")
+            ]),
+            "CASE42_DESKTOP-12345_File_Report_20230307T015244.json"
+        );
+        assert!("{host}_{hostname}.{ext}"
+            .parse::<FilenameTemplate>()
+            .is_err());
+        assert!("{host".parse::<FilenameTemplate>().is_err());
     }
 
-    fn insert_int_val(&self, f: &str, n: u64) {
-        self.update_field_with_value(f, n.to_string());
+    #[test]
+    fn test_filename_template_same_path() {
+        use crate::report::{ClobberPolicy, FilenameTemplate};
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_same_path").unwrap();
+        let template: FilenameTemplate = "{host}_{report}.{ext}".parse().unwrap();
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile)
+                .unwrap()
+                .with_filename_template(Some(template.clone()));
+        let db1 = Path::new("C1/Windows.edb");
+        let db2 = Path::new("C2/Windows.edb");
+        let path = rep_producer
+            .report_path(ReportFormat::Json, Some(db1), "host", "File_Report", None)
+            .unwrap();
+        assert_eq!(path, dir.path().join("host_File_Report.json"));
+        // the file of the first database isn't created yet, it still must not be reused
+        assert!(rep_producer
+            .report_path(ReportFormat::Json, Some(db2), "host", "File_Report", None)
+            .is_err());
+
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile)
+                .unwrap()
+                .with_filename_template(Some(template))
+                .with_clobber(ClobberPolicy::Rename);
+        let path1 = rep_producer
+            .report_path(ReportFormat::Json, Some(db1), "host", "File_Report", None)
+            .unwrap();
+        let path2 = rep_producer
+            .report_path(ReportFormat::Json, Some(db2), "host", "File_Report", None)
+            .unwrap();
+        assert_eq!(path1, dir.path().join("host_File_Report.json"));
+        assert_eq!(path2, dir.path().join("host_File_Report_1.json"));
     }
 
-    fn set_field(&self, f: &str) {
-        // set field with empty value to record field name
-        self.update_field_with_value(f, "".to_string());
+    #[test]
+    fn test_unknown_hostname() {
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_hostname").unwrap();
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Csv, ReportOutput::ToFile).unwrap();
+        let (paths, _) = rep_producer
+            .new_report(Path::new(""), " ", "File_Report", None)
+            .unwrap();
+        let name = paths[0].file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("Unknown_File_Report_"), "{name}");
     }
 
-    fn is_some_val_in_record(&self) -> bool {
-        self.values.borrow().iter().any(|i| !i.1.is_empty())
+    #[test]
+    fn test_per_host_dirs() {
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_per_host").unwrap();
+        let rep_producer = ReportProducer::new(dir.path(), ReportFormat::Csv, ReportOutput::ToFile)
+            .unwrap()
+            .with_per_host_dirs(true);
+        let (paths, _) = rep_producer
+            .new_report(
+                Path::new("Windows.db"),
+                "DESKTOP-12345",
+                "File_Report",
+                None,
+            )
+            .unwrap();
+        let path = &paths[0];
+        assert_eq!(path.parent().unwrap(), dir.path().join("DESKTOP-12345"));
+        assert!(path.parent().unwrap().is_dir());
     }
-}
 
-impl Drop for ReportCsv {
-    fn drop(&mut self) {
-        self.footer();
+    #[test]
+    fn test_formats() {
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_formats").unwrap();
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile)
+                .unwrap()
+                .with_formats(vec![
+                    ReportFormat::Json,
+                    ReportFormat::Csv,
+                    ReportFormat::Json,
+                ]);
+        let (paths, mut rep) = rep_producer
+            .new_report(Path::new("Windows.db"), "host", "File_Report", None)
+            .unwrap();
+        rep.insert_int_val("WorkId", 1);
+        rep.create_new_row();
+        drop(rep);
+        assert_eq!(paths.len(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&paths[0]).unwrap(),
+            "{\"WorkId\":1}\n"
+        );
+        assert!(paths[1].to_string_lossy().ends_with(".csv"));
+        assert_eq!(std::fs::read_to_string(&paths[1]).unwrap(), "WorkId\n1");
+        let summary = rep_producer.summary();
+        assert_eq!(summary.len(), 2);
+        assert!(summary.iter().all(|s| s.records == 1));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::report::{
-        Report, ReportCsv, ReportFormat, ReportJson, ReportOutput, ReportProducer, ReportSuffix,
-    };
-    use chrono::{DateTime, NaiveDate, Utc};
-    use ese_parser_lib::parser::jet::DbState;
-    use std::path::Path;
+    #[test]
+    fn test_record_hook() {
+        use std::sync::{Arc, Mutex};
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_record_hook").unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = seen.clone();
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile)
+                .unwrap()
+                .with_count_only(true)
+                .with_record_hook(Some(Box::new(move |report, fields| {
+                    hook_seen
+                        .lock()
+                        .unwrap()
+                        .push((report.to_string(), fields.to_vec()));
+                })));
+        let (_, mut rep) = rep_producer
+            .new_report(Path::new("Windows.db"), "host", "File_Report", None)
+            .unwrap();
+        rep.insert_int_val("WorkId", 1);
+        rep.insert_str_val("System_ItemName", "a.txt".into());
+        rep.create_new_row();
+        drop(rep);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(
+                "File_Report".to_string(),
+                vec![
+                    ("WorkId".to_string(), "1".to_string()),
+                    ("System_ItemName".to_string(), "a.txt".to_string())
+                ]
+            )]
+        );
+    }
 
     #[test]
-    pub fn test_report_csv() {
-        let p = Path::new("test.csv");
-        let report_type = ReportOutput::ToFile;
-        let report_suffix = None;
+    fn test_report_formats() {
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_report_formats").unwrap();
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile)
+                .unwrap()
+                .with_report_formats(vec![(ReportKind::File, ReportFormat::Csv)]);
+        let ext = |report_suffix| {
+            let (paths, _) = rep_producer
+                .new_report(Path::new("Windows.db"), "host", report_suffix, None)
+                .unwrap();
+            assert_eq!(paths.len(), 1);
+            paths[0].extension().unwrap().to_string_lossy().to_string()
+        };
+        assert_eq!(ext("File_Report"), "csv");
+        assert_eq!(ext("Internet_History_Report"), "json");
+        assert_eq!(ext("SystemIndex_Gthr"), "json");
+    }
+
+    #[test]
+    fn test_count_only() {
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_count").unwrap();
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile)
+                .unwrap()
+                .with_count_only(true);
         {
-            let mut r = ReportCsv::new(p, report_type, report_suffix).unwrap();
-            r.set_field("int_field");
-            r.set_field("str_field");
-            r.insert_int_val("int_field", 0);
-            r.insert_str_val("str_field", "string0".into());
-            for i in 1..10 {
-                r.create_new_row();
-                if i % 2 == 0 {
-                    r.insert_str_val("str_field", format!("string{}", i));
-                } else {
-                    r.insert_int_val("int_field", i);
-                }
+            let (_, mut rep) = rep_producer
+                .new_report(
+                    Path::new("Windows.db"),
+                    "DESKTOP-12345",
+                    "File_Report",
+                    None,
+                )
+                .unwrap();
+            for work_id in 1..=3 {
+                rep.insert_int_val("WorkId", work_id);
+                rep.create_new_row();
             }
         }
-        let data = std::fs::read_to_string(p).unwrap();
-        let expected = r#"int_field,str_field
-0,"string0"
-1,
-,"string2"
-3,
-,"string4"
-5,
-,"string6"
-7,
-,"string8"
-9,"#;
-        assert_eq!(data, expected);
-        std::fs::remove_file(p).unwrap();
+        rep_producer.finish().unwrap();
+        let summary = rep_producer.summary();
+        assert_eq!(summary.len(), 1);
+        assert_eq!(summary[0].records, 3);
+        assert_eq!(summary[0].path, None);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
     }
 
     #[test]
-    pub fn test_report_jsonl() {
-        let p = Path::new("test.json");
-        let report_type = ReportOutput::ToFile;
-        let report_suffix = Some(ReportSuffix::FileReport);
-        {
-            let mut r = ReportJson::new(p, report_type, report_suffix).unwrap();
-            r.insert_int_val("int_field", 0);
-            r.insert_str_val("str_field", "string0_with_escapes_here1\"here2\\".into());
-            for i in 1..10 {
-                r.create_new_row();
-                if i % 2 == 0 {
-                    r.insert_str_val("str_field", format!("string{}", i));
-                } else {
-                    r.insert_int_val("int_field", i);
-                }
+    fn test_outdir_is_file() {
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_outdir").unwrap();
+        let file = dir.path().join("report.json");
+        std::fs::write(&file, b"").unwrap();
+        let err = ReportProducer::new(&file, ReportFormat::Json, ReportOutput::ToFile)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("is not a directory"), "{err}");
+    }
+
+    #[test]
+    fn test_zip() {
+        use std::io::Read;
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_zip").unwrap();
+        let rep_producer = ReportProducer::new(dir.path(), ReportFormat::Csv, ReportOutput::ToFile)
+            .unwrap()
+            .with_archive(Some(ReportArchive::Zip), true);
+        let (paths, mut rep) = rep_producer
+            .new_report(
+                Path::new("Windows.db"),
+                "DESKTOP-12345",
+                "File_Report",
+                None,
+            )
+            .unwrap();
+        rep.insert_int_val("WorkId", 1);
+        rep.create_new_row();
+        drop(rep);
+        rep_producer.finish().unwrap();
+        let files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1, "{files:?}");
+        let name = files[0].file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("DESKTOP-12345_SIDR_"), "{name}");
+        assert!(name.ends_with(".zip"), "{name}");
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&files[0]).unwrap()).unwrap();
+        assert!(zip.by_name("manifest.json").is_ok());
+        let report = paths[0].file_name().unwrap().to_string_lossy();
+        let mut data = String::new();
+        zip.by_name(&report)
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "WorkId\n1");
+    }
+
+    #[test]
+    fn test_tar_gz() {
+        use flate2::read::GzDecoder;
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_tar").unwrap();
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile)
+                .unwrap()
+                .with_archive(Some(ReportArchive::TarGz), false);
+        let (paths, mut rep) = rep_producer
+            .new_report(
+                Path::new("Windows.db"),
+                "DESKTOP-12345",
+                "File_Report",
+                None,
+            )
+            .unwrap();
+        rep.insert_int_val("WorkId", 1);
+        rep.create_new_row();
+        drop(rep);
+        rep_producer.finish().unwrap();
+        // the loose files are kept
+        assert!(paths[0].exists());
+        let archive = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.to_string_lossy().ends_with(".tar.gz"))
+            .unwrap();
+        let mut tar = tar::Archive::new(GzDecoder::new(std::fs::File::open(archive).unwrap()));
+        let mut names: Vec<String> = tar
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        let report = paths[0].file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!(names, vec![report, "manifest.json".to_string()]);
+    }
+
+    #[test]
+    fn test_merge() {
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("sidr_merge").unwrap();
+        let rep_producer =
+            ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile)
+                .unwrap()
+                .with_merge(true);
+        std::thread::scope(|scope| {
+            scope.spawn(|| rep_producer.write_merged_reports());
+            let mut paths = Vec::new();
+            for (db, host) in [
+                ("C:\\a\\Windows.edb", "HOST-A"),
+                ("C:\\b\\Windows.db", "HOST-B"),
+            ] {
+                let (path, mut rep) = rep_producer
+                    .new_report(Path::new(db), host, "File_Report", None)
+                    .unwrap();
+                rep.insert_int_val("WorkId", 1);
+                rep.create_new_row();
+                paths.push(path[0].clone());
             }
-        }
-        let data = std::fs::read_to_string(p).unwrap();
-        let expected = r#"{"int_field":0,"str_field":"string0_with_escapes_here1\"here2\\"}
-{"int_field":1}
-{"str_field":"string2"}
-{"int_field":3}
-{"str_field":"string4"}
-{"int_field":5}
-{"str_field":"string6"}
-{"int_field":7}
-{"str_field":"string8"}
-{"int_field":9}
-"#;
-        assert_eq!(data, expected);
-        std::fs::remove_file(p).unwrap();
+            assert_eq!(paths[0], paths[1]);
+            rep_producer.close_merged_reports();
+        });
+        let path = &rep_producer.merged_paths.lock().unwrap()[0].1;
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("All_Hosts_File_Report_"), "{name}");
+        let data = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<_> = data.lines().collect();
+        assert_eq!(lines.len(), 2, "{data}");
+        assert!(lines[0].contains("\"_host\":\"HOST-A\""), "{data}");
+        assert!(lines[1].contains("\"_host\":\"HOST-B\""), "{data}");
     }
 
     #[test]
@@ -500,7 +3988,7 @@ mod tests {
     #[test]
     fn test_get_path_db_status() {
         let path = Path::new("./tests");
-        let rp = ReportProducer::new(path, ReportFormat::Json, ReportOutput::ToStdout);
+        let rp = ReportProducer::new(path, ReportFormat::Json, ReportOutput::ToStdout).unwrap();
         let naivedatetime_utc = NaiveDate::from_ymd_opt(2000, 1, 12)
             .unwrap()
             .and_hms_opt(2, 0, 0)
@@ -512,7 +4000,8 @@ mod tests {
                 "activity",
                 dt,
                 "edb.test",
-                Some(DbState::CleanShutdown)
+                Some(DbState::CleanShutdown),
+                None
             )
             .to_string_lossy(),
             Path::new("./tests")
@@ -525,19 +4014,38 @@ mod tests {
                 "activity",
                 dt,
                 "edb.test",
-                Some(DbState::DirtyShutdown)
+                Some(DbState::DirtyShutdown),
+                None
             )
             .to_string_lossy(),
             Path::new("./tests")
                 .join("test_hostname_activity_20000112_020000_dirty.edb.test")
                 .to_string_lossy()
         );
+        assert_eq!(
+            rp.get_path_db_status(
+                "test_hostname",
+                "activity",
+                dt,
+                "edb.test",
+                Some(DbState::DirtyShutdown),
+                Some("0123abcd")
+            )
+            .to_string_lossy(),
+            Path::new("./tests")
+                .join("test_hostname_activity_20000112_020000_0123abcd_dirty.edb.test")
+                .to_string_lossy()
+        );
+        assert_ne!(
+            super::db_tag(Path::new("C:\\image1\\Windows.edb")),
+            super::db_tag(Path::new("C:\\image2\\Windows.edb"))
+        );
     }
 
     #[test]
     fn test_is_db_dirty() {
         let path = Path::new("./tests");
-        let rp = ReportProducer::new(path, ReportFormat::Json, ReportOutput::ToStdout);
+        let rp = ReportProducer::new(path, ReportFormat::Json, ReportOutput::ToStdout).unwrap();
         assert_eq!(rp.is_db_dirty(Some(DbState::CleanShutdown)), false);
         assert_eq!(rp.is_db_dirty(Some(DbState::DirtyShutdown)), true);
         assert_eq!(rp.is_db_dirty(Some(DbState::BeingConverted)), true);
@@ -0,0 +1,996 @@
+use flate2::read::GzDecoder;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{error, info, warn};
+use serde::Serialize;
+use simple_error::SimpleError;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf, Prefix};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use tempdir::TempDir;
+use walkdir::WalkDir;
+
+use crate::ese::*;
+use crate::report::*;
+use crate::shared::*;
+use crate::sqlite::*;
+
+#[derive(Default)]
+pub struct ScanOptions {
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+    pub fail_fast: bool,   // stop at the first unreadable entry or database
+    pub quiet: bool,       // don't list the processed databases and their reports on stdout
+    pub copy_locked: bool, // parse a copy of locked databases taken from a volume shadow copy
+    pub ignore_wal: bool,  // leave the -wal/-shm files of SQLite databases out
+    pub ignore_dirs: Vec<String>, // names of directories not to descend into
+    pub include_hidden: bool, // scan entries whose name starts with a dot too
+    pub max_db_size: Option<u64>, // skip databases larger than this (bytes) while scanning directories
+}
+
+fn dump(
+    input_dir: &PathBuf,
+    scan_opts: &ScanOptions,
+    databases: &mut Vec<(PathBuf, DbKind)>,
+) -> Result<(), SimpleError> {
+    // when following links walkdir checks every directory against its ancestors
+    // and reports symlink loops as errors, which breaks the cycles
+    let mut walker = WalkDir::new(input_dir).follow_links(scan_opts.follow_symlinks);
+    if let Some(max_depth) = scan_opts.max_depth {
+        // walkdir counts the input directory itself as depth 0, so its files are at depth 1
+        walker = walker.max_depth(max_depth.saturating_add(1));
+    }
+    // the input directory itself is always scanned
+    let walker = walker.into_iter().filter_entry(|e| {
+        e.depth() == 0
+            || !(is_hidden(e.file_name(), scan_opts)
+                || (e.file_type().is_dir() && is_ignored_dir(e.file_name(), scan_opts)))
+    });
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if e.depth() == 0 => {
+                return Err(SimpleError::new(format!(
+                    "Could not read dir '{}': {e}",
+                    input_dir.to_string_lossy()
+                )))
+            }
+            Err(e) if scan_opts.fail_fast => return Err(SimpleError::new(format!("{e}"))),
+            Err(e) => {
+                // unreadable subdirectories, dangling symlinks, files removed during the scan, etc.
+                // shouldn't abort the whole scan
+                warn!("{e}. Skipping it.");
+                continue;
+            }
+        };
+        if entry.file_type().is_file() {
+            if let Some(kind) = get_db_kind(entry.path()) {
+                if is_too_large(&entry, scan_opts) {
+                    continue;
+                }
+                databases.push((entry.into_path(), kind));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// A decoy or corrupted multi-terabyte database would keep a worker busy for hours.
+fn is_too_large(entry: &walkdir::DirEntry, scan_opts: &ScanOptions) -> bool {
+    let Some(max_db_size) = scan_opts.max_db_size else {
+        return false;
+    };
+    match entry.metadata() {
+        Ok(metadata) if metadata.len() > max_db_size => {
+            warn!(
+                "'{}' is {} bytes, larger than --max-db-size {max_db_size}. Skipping it.",
+                entry.path().to_string_lossy(),
+                metadata.len()
+            );
+            true
+        }
+        _ => false,
+    }
+}
+
+// Only the dot prefix of Unix is portable, the hidden attribute of Windows isn't checked.
+fn is_hidden(name: &std::ffi::OsStr, scan_opts: &ScanOptions) -> bool {
+    !scan_opts.include_hidden && name.to_string_lossy().starts_with('.')
+}
+
+// Directory names are matched case insensitively, as on Windows.
+fn is_ignored_dir(name: &std::ffi::OsStr, scan_opts: &ScanOptions) -> bool {
+    let name = name.to_string_lossy();
+    scan_opts
+        .ignore_dirs
+        .iter()
+        .any(|d| d.eq_ignore_ascii_case(&name))
+}
+
+// Returns the databases that failed, so they can be summed up at the end of the run
+// instead of getting lost among the progress output.
+fn process_databases(
+    databases: &[(PathBuf, DbKind)],
+    threads: usize,
+    report_prod: &ReportProducer,
+    scan_opts: &ScanOptions,
+) -> Vec<(PathBuf, SimpleError)> {
+    let report_type = report_prod.get_report_type();
+    // all reports share the same stream in stdout mode, so don't let their records interleave
+    let threads = match report_type {
+        ReportOutput::ToStdout => 1,
+        ReportOutput::ToFile => threads.clamp(1, databases.len().max(1)),
+    };
+    let next_db = AtomicUsize::new(0);
+    let failed = Mutex::new(Vec::new());
+    let stop = AtomicBool::new(false);
+    let progress = new_progress_bar(databases.len(), scan_opts.quiet);
+    thread::scope(|scope| {
+        if report_prod.is_merged() {
+            scope.spawn(|| report_prod.write_merged_reports());
+        }
+        let mut workers = Vec::new();
+        for _ in 0..threads {
+            workers.push(scope.spawn(|| {
+                let mut status_logger = new_status_logger(report_type, scan_opts.quiet);
+                while let Some((p, kind)) = databases.get(next_db.fetch_add(1, Ordering::Relaxed)) {
+                    if stop.load(Ordering::Relaxed) || is_interrupted() {
+                        break;
+                    }
+                    progress.set_message(p.to_string_lossy().into_owned());
+                    let res = process_db(p, *kind, scan_opts, report_prod, &mut status_logger);
+                    if let Err(e) = res {
+                        failed.lock().unwrap().push((p.clone(), e));
+                        if scan_opts.fail_fast {
+                            stop.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    progress.inc(1);
+                }
+            }));
+        }
+        let results: Vec<_> = workers.into_iter().map(|w| w.join()).collect();
+        // the merged reports are written until the last sender is gone
+        report_prod.close_merged_reports();
+        for res in results {
+            if let Err(panic) = res {
+                std::panic::resume_unwind(panic);
+            }
+        }
+    });
+    progress.finish_and_clear();
+    let mut failed = failed.into_inner().unwrap();
+    failed.sort_by(|a, b| a.0.cmp(&b.0));
+    failed
+}
+
+// Drawn to stderr, so it stays out of reports written to stdout. indicatif doesn't draw it
+// at all when stderr is not a terminal. A single database gets none.
+fn new_progress_bar(len: usize, quiet: bool) -> ProgressBar {
+    if quiet || len < 2 {
+        return ProgressBar::hidden();
+    }
+    let progress = ProgressBar::with_draw_target(Some(len as u64), ProgressDrawTarget::stderr());
+    progress.set_style(
+        ProgressStyle::with_template("[{bar:30}] {pos}/{len} {wide_msg}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    progress
+}
+
+// stdout belongs to the report data in stdout mode, the status goes to stderr then
+fn new_status_logger(report_type: ReportOutput, quiet: bool) -> Box<dyn Write> {
+    match report_type {
+        _ if quiet => Box::new(std::io::sink()),
+        ReportOutput::ToStdout => Box::new(std::io::stderr()),
+        ReportOutput::ToFile => Box::new(std::io::stdout()),
+    }
+}
+
+fn process_db(
+    p: &Path,
+    kind: DbKind,
+    scan_opts: &ScanOptions,
+    report_prod: &ReportProducer,
+    status_logger: &mut Box<dyn Write>,
+) -> Result<(), SimpleError> {
+    if is_gzip(p) {
+        let (_tmp_dir, db) = gunzip_db(p)?;
+        return process_db_file(&db, kind, report_prod, status_logger);
+    }
+    let sidecars = match kind {
+        DbKind::Sqlite => sqlite_sidecars(p),
+        DbKind::Ese => Vec::new(),
+    };
+    let copied_sidecars = if scan_opts.ignore_wal {
+        &[][..]
+    } else {
+        &sidecars[..]
+    };
+    if is_locked(p) {
+        if !scan_opts.copy_locked {
+            return Err(SimpleError::new(format!(
+                "'{}' is locked by another process (e.g. the Windows Search service), use --copy-locked to parse a copy of it from a volume shadow copy",
+                p.to_string_lossy()
+            )));
+        }
+        warn!(
+            "'{}' is locked, parsing a copy of it taken from a volume shadow copy",
+            p.to_string_lossy()
+        );
+        let (_tmp_dir, copy) = copy_db_from_shadow(p, copied_sidecars)?;
+        return process_db_file(&copy, kind, report_prod, status_logger);
+    }
+    if sidecars.is_empty() {
+        return process_db_file(p, kind, report_prod, status_logger);
+    }
+    // SQLite replays the WAL on open: in a copy, so the evidence is never written to,
+    // or leave it out of the copy, as opening the original would still replay it
+    info!(
+        "'{}' has a write-ahead log, parsing a copy of it {} the log",
+        p.to_string_lossy(),
+        if scan_opts.ignore_wal {
+            "without"
+        } else {
+            "with"
+        }
+    );
+    let files: Vec<PathBuf> = std::iter::once(p.to_path_buf())
+        .chain(copied_sidecars.iter().cloned())
+        .collect();
+    let (_tmp_dir, copy) = copy_db(&files)?;
+    process_db_file(&copy, kind, report_prod, status_logger)
+}
+
+// The -wal and -shm files SQLite keeps next to a database in WAL mode (Windows.db-wal, ...).
+fn sqlite_sidecars(p: &Path) -> Vec<PathBuf> {
+    ["-wal", "-shm"]
+        .iter()
+        .map(|suffix| {
+            let mut name = p.as_os_str().to_owned();
+            name.push(suffix);
+            PathBuf::from(name)
+        })
+        .filter(|sidecar| sidecar.is_file())
+        .collect()
+}
+
+fn process_db_file(
+    p: &Path,
+    kind: DbKind,
+    report_prod: &ReportProducer,
+    status_logger: &mut Box<dyn Write>,
+) -> Result<(), SimpleError> {
+    match kind {
+        DbKind::Ese if report_prod.is_raw() => ese_dump_raw(p, report_prod, status_logger),
+        DbKind::Sqlite if report_prod.is_raw() => sqlite_dump_raw(p, report_prod, status_logger),
+        DbKind::Ese => ese_generate_report(p, report_prod, status_logger),
+        DbKind::Sqlite => sqlite_generate_report(p, report_prod, status_logger),
+    }
+}
+
+pub fn list_tables(p: &Path, kind: DbKind) -> Result<Vec<(String, Vec<String>)>, SimpleError> {
+    if is_gzip(p) {
+        let (_tmp_dir, db) = gunzip_db(p)?;
+        return list_tables(&db, kind);
+    }
+    match kind {
+        DbKind::Ese => ese_list_tables(p),
+        DbKind::Sqlite => sqlite_list_tables(p),
+    }
+}
+
+// Windows.edb is kept open by the Windows Search service on a live system. The service
+// doesn't share it for reading, so it can't be opened or even copied, only read from a
+// volume shadow copy.
+fn is_locked(p: &Path) -> bool {
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+    if !cfg!(windows) {
+        return false;
+    }
+    match fs::File::open(p) {
+        Ok(_) => false,
+        Err(e) => matches!(
+            e.raw_os_error(),
+            Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+        ),
+    }
+}
+
+// Copies a database, followed by its sidecar files, into a temporary directory, which is
+// removed when the returned TempDir is dropped. Sidecar files keep their names, so they still
+// belong to the copied database.
+fn copy_db(files: &[PathBuf]) -> Result<(TempDir, PathBuf), SimpleError> {
+    let tmp_dir = TempDir::new("sidr").map_err(|e| SimpleError::new(format!("{e}")))?;
+    let mut copy = PathBuf::new();
+    for f in files {
+        let to = tmp_dir.path().join(f.file_name().unwrap_or_default());
+        fs::copy(f, &to).map_err(|e| {
+            SimpleError::new(format!(
+                "Could not copy '{}' to a temporary directory: {e}",
+                f.to_string_lossy()
+            ))
+        })?;
+        if copy.as_os_str().is_empty() {
+            copy = to;
+        }
+    }
+    Ok((tmp_dir, copy))
+}
+
+// Copies a locked database out of a volume shadow copy of its drive, which is deleted again
+// once the files are copied.
+fn copy_db_from_shadow(p: &Path, sidecars: &[PathBuf]) -> Result<(TempDir, PathBuf), SimpleError> {
+    let (drive, _) = split_drive(p)?;
+    let shadow = ShadowCopy::create(drive)?;
+    let mut files = Vec::new();
+    for f in std::iter::once(p).chain(sidecars.iter().map(|s| s.as_path())) {
+        let (_, rest) = split_drive(f)?;
+        files.push(shadow.path_of(&rest));
+    }
+    copy_db(&files)
+}
+
+// The drive letter of a file and its path below the root of the drive.
+fn split_drive(p: &Path) -> Result<(char, PathBuf), SimpleError> {
+    let not_local = || {
+        SimpleError::new(format!(
+            "'{}' is not on a local drive, it can't be read from a shadow copy",
+            p.to_string_lossy()
+        ))
+    };
+    // opens the file without asking for read access, which a locked file allows
+    let full = fs::canonicalize(p)
+        .map_err(|e| SimpleError::new(format!("Can't resolve '{}': {e}", p.to_string_lossy())))?;
+    let mut components = full.components();
+    let drive = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(d) | Prefix::VerbatimDisk(d) => d as char,
+            _ => return Err(not_local()),
+        },
+        _ => return Err(not_local()),
+    };
+    let rest = components
+        .filter(|c| !matches!(c, Component::RootDir))
+        .collect();
+    Ok((drive, rest))
+}
+
+// A volume shadow copy made with the WMI Win32_ShadowCopy class, deleted when dropped.
+// Creating one needs administrator rights.
+struct ShadowCopy {
+    id: String,
+    device: String, // \\?\GLOBALROOT\Device\HarddiskVolumeShadowCopyN
+}
+
+impl ShadowCopy {
+    fn create(drive: char) -> Result<Self, SimpleError> {
+        let script = format!(
+            "$r = (Get-WmiObject -List Win32_ShadowCopy).Create('{drive}:\\', 'ClientAccessible'); \
+             if ($r.ReturnValue -ne 0) {{ Write-Error \"Win32_ShadowCopy.Create returned $($r.ReturnValue)\"; exit 1 }}; \
+             $s = Get-WmiObject Win32_ShadowCopy | Where-Object {{ $_.ID -eq $r.ShadowID }}; \
+             $s.ID; $s.DeviceObject"
+        );
+        let err = |e: String| {
+            SimpleError::new(format!(
+                "Could not create a shadow copy of {drive}: (it needs administrator rights): {e}"
+            ))
+        };
+        let out = powershell(&script).map_err(err)?;
+        let mut lines = out.lines().map(str::trim).filter(|l| !l.is_empty());
+        match (lines.next(), lines.next()) {
+            (Some(id), Some(device)) => Ok(ShadowCopy {
+                id: id.to_string(),
+                device: device.to_string(),
+            }),
+            _ => Err(err(format!("unexpected output '{}'", out.trim()))),
+        }
+    }
+
+    fn path_of(&self, rest: &Path) -> PathBuf {
+        PathBuf::from(format!("{}\\{}", self.device, rest.to_string_lossy()))
+    }
+}
+
+impl Drop for ShadowCopy {
+    fn drop(&mut self) {
+        let script = format!(
+            "Get-WmiObject Win32_ShadowCopy | Where-Object {{ $_.ID -eq '{}' }} | ForEach-Object {{ $_.Delete() }}",
+            self.id
+        );
+        if let Err(e) = powershell(&script) {
+            warn!("Could not delete the shadow copy {}: {e}", self.id);
+        }
+    }
+}
+
+fn powershell(script: &str) -> Result<String, String> {
+    let out = Command::new("powershell.exe")
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .output()
+        .map_err(|e| format!("can't run powershell.exe: {e}"))?;
+    if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+// Collected databases are often stored compressed (Windows.edb.gz).
+fn is_gzip(p: &Path) -> bool {
+    p.extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("gz"))
+}
+
+// Decompresses a database into a temporary directory, which is removed when the returned
+// TempDir is dropped. The copy is named like the database without the .gz extension.
+fn gunzip_db(p: &Path) -> Result<(TempDir, PathBuf), SimpleError> {
+    let tmp_dir = TempDir::new("sidr").map_err(|e| SimpleError::new(format!("{e}")))?;
+    let to = tmp_dir.path().join(p.file_stem().unwrap_or_default());
+    let err = |e: std::io::Error| {
+        SimpleError::new(format!(
+            "Could not decompress '{}' to a temporary directory: {e}",
+            p.to_string_lossy()
+        ))
+    };
+    let mut decoder = GzDecoder::new(fs::File::open(p).map_err(err)?);
+    let mut out = fs::File::create(&to).map_err(err)?;
+    std::io::copy(&mut decoder, &mut out).map_err(err)?;
+    Ok((tmp_dir, to))
+}
+
+// Windows.edb/Windows.db (and S-1-* per-user copies) are recognized by name,
+// anything else (renamed or carved copies) is recognized by its file signature.
+// Compressed databases are only recognized by name (Windows.edb.gz).
+fn get_db_kind(p: &Path) -> Option<DbKind> {
+    if is_gzip(p) {
+        let db = p.with_extension("");
+        return Some(db.as_path())
+            .filter(|db| is_valid_file(db))
+            .and_then(get_db_kind);
+    }
+    if !is_valid_file(p) {
+        return detect_db_type(p);
+    }
+    let ext = p
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|s| s.to_lowercase());
+    match ext.as_deref() {
+        Some("edb") => Some(DbKind::Ese),
+        Some("db") => Some(DbKind::Sqlite),
+        _ => None,
+    }
+}
+
+fn is_valid_file(p: &Path) -> bool {
+    let is_valid_name = p
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .map_or(false, |name| {
+            name.eq_ignore_ascii_case("windows") || name.to_ascii_lowercase().starts_with("s-1-")
+        });
+    let is_valid_ext = p.extension().and_then(|e| e.to_str()).map_or(false, |ext| {
+        ext.to_ascii_lowercase() == "edb" || ext.to_ascii_lowercase() == "db"
+    });
+    is_valid_name && is_valid_ext
+}
+
+// The databases the inputs contain, in the order they are processed.
+pub fn find_databases(
+    inputs: &[PathBuf],
+    scan_opts: &ScanOptions,
+) -> Result<Vec<(PathBuf, DbKind)>, SimpleError> {
+    let mut databases = Vec::new();
+    for input in inputs {
+        process_input(input, scan_opts, &mut databases)?;
+    }
+    Ok(databases)
+}
+
+// What a run did, for --summary-json and for programs using SIDR as a library.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct RunReport {
+    pub found: usize,  // databases found in the inputs
+    pub failed: usize, // databases that could not be processed
+    pub reports: Vec<ReportSummary>,
+    pub databases: Vec<DatabaseReport>,
+}
+
+// A database found in the inputs, with the records it gave every report.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DatabaseReport {
+    pub path: PathBuf,
+    pub kind: DbKind,
+    pub error: Option<String>, // why it could not be processed
+    pub reports: Vec<ReportSummary>,
+}
+
+// Options of process_directory; write_reports takes a ReportProducer with all the others.
+pub struct ProcessOptions {
+    pub outdir: PathBuf,
+    pub format: ReportFormat,
+    pub report_type: ReportOutput,
+    pub threads: usize,
+    pub scan: ScanOptions,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        ProcessOptions {
+            outdir: PathBuf::from("."),
+            format: ReportFormat::Json,
+            report_type: ReportOutput::ToFile,
+            threads: 1,
+            scan: ScanOptions::default(),
+        }
+    }
+}
+
+// Writes the reports of the databases in a directory (or of a single database file) and
+// returns what was found and written, for programs using SIDR as a library.
+pub fn process_directory(path: &Path, opts: &ProcessOptions) -> Result<RunReport, SimpleError> {
+    let rep_producer = ReportProducer::new(&opts.outdir, opts.format, opts.report_type)?;
+    write_reports(
+        &rep_producer,
+        &[path.to_path_buf()],
+        &opts.scan,
+        opts.threads,
+    )
+}
+
+pub fn write_reports(
+    rep_producer: &ReportProducer,
+    inputs: &[PathBuf],
+    scan_opts: &ScanOptions,
+    threads: usize,
+) -> Result<RunReport, SimpleError> {
+    let databases = find_databases(inputs, scan_opts)?;
+    let failed = process_databases(&databases, threads, rep_producer, scan_opts);
+    rep_producer.finish()?;
+    if scan_opts.fail_fast {
+        if let Some((p, e)) = failed.first() {
+            return Err(SimpleError::new(format!(
+                "Failed to process '{}': {e}",
+                p.to_string_lossy()
+            )));
+        }
+    }
+    if !databases.is_empty() {
+        log_summary(databases.len(), &failed);
+    }
+    let reports = rep_producer.summary();
+    // the counts are what --count prints
+    if !rep_producer.is_count_only() {
+        write_records_summary(
+            &mut new_status_logger(rep_producer.get_report_type(), scan_opts.quiet),
+            &reports,
+        )
+        .map_err(|e| SimpleError::new(format!("{e}")))?;
+    }
+    let databases_report = databases
+        .iter()
+        .map(|(p, kind)| DatabaseReport {
+            path: p.clone(),
+            kind: *kind,
+            error: failed
+                .iter()
+                .find(|(f, _)| f == p)
+                .map(|(_, e)| e.to_string()),
+            reports: rep_producer.db_summary(p),
+        })
+        .collect();
+    Ok(RunReport {
+        found: databases.len(),
+        failed: failed.len(),
+        reports,
+        databases: databases_report,
+    })
+}
+
+fn write_records_summary(
+    logger: &mut Box<dyn Write>,
+    reports: &[ReportSummary],
+) -> std::io::Result<()> {
+    if reports.is_empty() {
+        return Ok(());
+    }
+    writeln!(logger, "\nRecords written:")?;
+    for r in reports {
+        // reports without records have no file
+        let path = r
+            .path
+            .as_ref()
+            .filter(|p| p.exists())
+            .map_or("-".into(), |p| p.to_string_lossy());
+        writeln!(logger, "{}\t{}\t{}\t{}", r.host, r.report, r.records, path)?;
+    }
+    Ok(())
+}
+
+// on stderr whatever the log level, the counts tell whether the run is complete
+fn log_summary(found: usize, failed: &[(PathBuf, SimpleError)]) {
+    eprintln!(
+        "Found {found} Windows Search database(s): {} processed, {} failed",
+        found - failed.len(),
+        failed.len()
+    );
+    for (p, e) in failed {
+        error!("Failed to process '{}': {e}", p.to_string_lossy());
+    }
+}
+
+fn process_input(
+    input: &PathBuf,
+    scan_opts: &ScanOptions,
+    databases: &mut Vec<(PathBuf, DbKind)>,
+) -> Result<(), SimpleError> {
+    let metadata = fs::metadata(input).map_err(|e| {
+        SimpleError::new(format!(
+            "Could not read input '{}': {e}",
+            input.to_string_lossy()
+        ))
+    })?;
+    if metadata.is_file() {
+        let kind = get_db_kind(input).ok_or_else(|| {
+            SimpleError::new(format!(
+                "Unsupported database file '{}'",
+                input.to_string_lossy()
+            ))
+        })?;
+        databases.push((input.clone(), kind));
+        Ok(())
+    } else {
+        dump(input, scan_opts, databases)
+    }
+}
+
+// Expands an input containing wildcards (e.g. C:\images\*\Windows\**\Windows.edb)
+// into the list of matched paths; any other input is passed through as is.
+pub fn expand_input(input: &Path) -> Result<Vec<PathBuf>, SimpleError> {
+    let pattern = input.to_string_lossy();
+    if !pattern.contains(['*', '?', '[']) {
+        return Ok(vec![input.to_path_buf()]);
+    }
+    let paths: Vec<PathBuf> = glob::glob(&pattern)
+        .map_err(|e| SimpleError::new(format!("Invalid input pattern '{pattern}': {e}")))?
+        .flatten()
+        .collect();
+    if paths.is_empty() {
+        return Err(SimpleError::new(format!(
+            "Input pattern '{pattern}' did not match anything"
+        )));
+    }
+    Ok(paths)
+}
+
+#[test]
+fn test_case_insensitive_db_names() {
+    use tempdir::TempDir;
+
+    assert!(is_valid_file(Path::new("windows.edb")));
+    assert!(is_valid_file(Path::new("WINDOWS.DB")));
+    assert!(!is_valid_file(Path::new("windows.txt")));
+
+    let input_dir = TempDir::new("sidr_input").unwrap();
+    let report_dir = TempDir::new("sidr_output").unwrap();
+    let db_path = input_dir.path().join("windows.db");
+    fs::copy("tests/testdata/Windows.db", &db_path).unwrap();
+    assert_eq!(get_db_kind(&db_path), Some(DbKind::Sqlite));
+
+    let rep_producer =
+        ReportProducer::new(report_dir.path(), ReportFormat::Json, ReportOutput::ToFile).unwrap();
+    let summary = write_reports(
+        &rep_producer,
+        &[input_dir.path().to_path_buf()],
+        &ScanOptions::default(),
+        1,
+    )
+    .unwrap();
+    assert_eq!((summary.found, summary.failed), (1, 0));
+    assert_eq!(summary.reports.len(), 3);
+    assert!(summary.reports.iter().all(|r| r.path.is_some()));
+    assert!(summary.reports.iter().any(|r| r.records > 0));
+    // 3 reports and the manifest
+    assert_eq!(fs::read_dir(report_dir.path()).unwrap().count(), 4);
+    assert!(report_dir.path().join("manifest.json").exists());
+}
+
+#[test]
+fn test_process_directory() {
+    use tempdir::TempDir;
+
+    let input_dir = TempDir::new("sidr_input").unwrap();
+    let report_dir = TempDir::new("sidr_output").unwrap();
+    fs::copy(
+        "tests/testdata/Windows.db",
+        input_dir.path().join("Windows.db"),
+    )
+    .unwrap();
+    let opts = ProcessOptions {
+        outdir: report_dir.path().to_path_buf(),
+        scan: ScanOptions {
+            quiet: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let run = process_directory(input_dir.path(), &opts).unwrap();
+    assert_eq!((run.found, run.failed), (1, 0));
+    assert_eq!(run.databases.len(), 1);
+    let db = &run.databases[0];
+    assert_eq!(db.path, input_dir.path().join("Windows.db"));
+    assert_eq!((db.kind, db.error.as_deref()), (DbKind::Sqlite, None));
+    assert_eq!(db.reports.len(), 3);
+    assert!(db.reports.iter().all(|r| r.path.as_ref().unwrap().exists()));
+    assert_eq!(
+        db.reports.iter().map(|r| r.records).sum::<usize>(),
+        run.reports.iter().map(|r| r.records).sum::<usize>()
+    );
+}
+
+#[test]
+fn test_gzip_db() {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tempdir::TempDir;
+
+    let input_dir = TempDir::new("sidr_input").unwrap();
+    let report_dir = TempDir::new("sidr_output").unwrap();
+    let db_path = input_dir.path().join("Windows.db.gz");
+    let mut encoder = GzEncoder::new(fs::File::create(&db_path).unwrap(), Compression::fast());
+    std::io::copy(
+        &mut fs::File::open("tests/testdata/Windows.db").unwrap(),
+        &mut encoder,
+    )
+    .unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(get_db_kind(&db_path), Some(DbKind::Sqlite));
+    assert_eq!(get_db_kind(Path::new("notes.txt.gz")), None);
+
+    let rep_producer =
+        ReportProducer::new(report_dir.path(), ReportFormat::Json, ReportOutput::ToFile).unwrap();
+    let summary = write_reports(
+        &rep_producer,
+        &[input_dir.path().to_path_buf()],
+        &ScanOptions::default(),
+        1,
+    )
+    .unwrap();
+    assert_eq!((summary.found, summary.failed), (1, 0));
+    assert!(summary.reports.iter().any(|r| r.records > 0));
+}
+
+#[test]
+fn test_reports_filter() {
+    use tempdir::TempDir;
+
+    let report_dir = TempDir::new("sidr_output").unwrap();
+    let rep_producer =
+        ReportProducer::new(report_dir.path(), ReportFormat::Json, ReportOutput::ToFile)
+            .unwrap()
+            .with_reports(vec![ReportKind::Internet]);
+    write_reports(
+        &rep_producer,
+        &[PathBuf::from("tests/testdata/Windows.db")],
+        &ScanOptions::default(),
+        1,
+    )
+    .unwrap();
+    let reports: Vec<String> = fs::read_dir(report_dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name != "manifest.json")
+        .collect();
+    assert_eq!(reports.len(), 1);
+    assert!(reports[0].contains("_Internet_History_Report_"));
+}
+
+#[test]
+fn test_max_depth() {
+    use tempdir::TempDir;
+
+    let input_dir = TempDir::new("sidr_input").unwrap();
+    let nested_dir = input_dir.path().join("nested");
+    fs::create_dir(&nested_dir).unwrap();
+    fs::copy("tests/testdata/Windows.db", nested_dir.join("Windows.db")).unwrap();
+
+    let input_dir = input_dir.path().to_path_buf();
+    for (max_depth, expected) in [(Some(0), 0), (Some(1), 1), (None, 1)] {
+        let scan_opts = ScanOptions {
+            max_depth,
+            ..Default::default()
+        };
+        let mut databases = Vec::new();
+        dump(&input_dir, &scan_opts, &mut databases).unwrap();
+        assert_eq!(databases.len(), expected);
+    }
+}
+
+#[test]
+fn test_ignore_dirs() {
+    use tempdir::TempDir;
+
+    let input_dir = TempDir::new("sidr_input").unwrap();
+    for dir in ["WinSxS", "Search"] {
+        let nested_dir = input_dir.path().join(dir);
+        fs::create_dir(&nested_dir).unwrap();
+        fs::copy("tests/testdata/Windows.db", nested_dir.join("Windows.db")).unwrap();
+    }
+
+    let scan_opts = ScanOptions {
+        ignore_dirs: vec!["winsxs".to_string()],
+        ..Default::default()
+    };
+    let mut databases = Vec::new();
+    dump(&input_dir.path().to_path_buf(), &scan_opts, &mut databases).unwrap();
+    assert_eq!(databases.len(), 1);
+    assert!(databases[0].0.starts_with(input_dir.path().join("Search")));
+}
+
+#[test]
+fn test_include_hidden() {
+    use tempdir::TempDir;
+
+    let input_dir = TempDir::new("sidr_input").unwrap();
+    let hidden_dir = input_dir.path().join(".snapshot");
+    fs::create_dir(&hidden_dir).unwrap();
+    fs::copy("tests/testdata/Windows.db", hidden_dir.join("Windows.db")).unwrap();
+    fs::copy(
+        "tests/testdata/Windows.db",
+        input_dir.path().join(".Windows.db"),
+    )
+    .unwrap();
+
+    let input_dir = input_dir.path().to_path_buf();
+    for (include_hidden, expected) in [(false, 0), (true, 2)] {
+        let scan_opts = ScanOptions {
+            include_hidden,
+            ..Default::default()
+        };
+        let mut databases = Vec::new();
+        dump(&input_dir, &scan_opts, &mut databases).unwrap();
+        assert_eq!(databases.len(), expected);
+    }
+}
+
+#[test]
+fn test_max_db_size() {
+    use tempdir::TempDir;
+
+    let input_dir = TempDir::new("sidr_input").unwrap();
+    fs::copy(
+        "tests/testdata/Windows.db",
+        input_dir.path().join("Windows.db"),
+    )
+    .unwrap();
+    let size = fs::metadata("tests/testdata/Windows.db").unwrap().len();
+
+    let input_dir = input_dir.path().to_path_buf();
+    for (max_db_size, expected) in [(Some(size - 1), 0), (Some(size), 1), (None, 1)] {
+        let scan_opts = ScanOptions {
+            max_db_size,
+            ..Default::default()
+        };
+        let mut databases = Vec::new();
+        dump(&input_dir, &scan_opts, &mut databases).unwrap();
+        assert_eq!(databases.len(), expected);
+    }
+}
+
+#[test]
+fn test_find_databases() {
+    let databases =
+        find_databases(&[PathBuf::from("tests/testdata")], &ScanOptions::default()).unwrap();
+    let mut kinds: Vec<DbKind> = databases.into_iter().map(|(_, kind)| kind).collect();
+    kinds.sort_by_key(|k| *k == DbKind::Sqlite);
+    assert_eq!(kinds, vec![DbKind::Ese, DbKind::Sqlite]);
+}
+
+#[test]
+fn test_sqlite_sidecars() {
+    use tempdir::TempDir;
+
+    let dir = TempDir::new("sidr_input").unwrap();
+    let db = dir.path().join("Windows.db");
+    fs::copy("tests/testdata/Windows.db", &db).unwrap();
+    assert!(sqlite_sidecars(&db).is_empty());
+    fs::write(dir.path().join("Windows.db-wal"), b"").unwrap();
+    assert_eq!(
+        sqlite_sidecars(&db),
+        vec![dir.path().join("Windows.db-wal")]
+    );
+    // the copy is parsed just like the original
+    let report_dir = TempDir::new("sidr_output").unwrap();
+    let rep_producer =
+        ReportProducer::new(report_dir.path(), ReportFormat::Json, ReportOutput::ToFile).unwrap();
+    let summary = write_reports(&rep_producer, &[db], &ScanOptions::default(), 1).unwrap();
+    assert_eq!((summary.found, summary.failed), (1, 0));
+}
+
+#[test]
+fn test_expand_input() {
+    assert_eq!(
+        expand_input(Path::new("tests/testdata")).unwrap(),
+        vec![PathBuf::from("tests/testdata")]
+    );
+    assert_eq!(
+        expand_input(Path::new("tests/testdata/*.edb")).unwrap(),
+        vec![PathBuf::from("tests/testdata/Windows.edb")]
+    );
+    assert!(expand_input(Path::new("tests/testdata/*.missing")).is_err());
+}
+
+#[test]
+fn warn_dirty() {
+    use ese_parser_lib::ese_parser::EseParser;
+
+    let report_dir = PathBuf::from("tests/testdata");
+    let rep_producer = ReportProducer::new(
+        report_dir.as_path(),
+        ReportFormat::Csv,
+        ReportOutput::ToFile,
+    )
+    .unwrap();
+    let ese_path = PathBuf::from("tests/testdata/Windows.edb");
+    assert!(ese_path.exists());
+    let jdb = Box::new(EseParser::load_from_path(10, ese_path).unwrap());
+    let edb_database_state = jdb.get_database_state();
+    assert!(rep_producer.is_db_dirty(Some(edb_database_state)));
+}
+
+#[test]
+fn test_generate_reports() {
+    use glob::glob;
+    use goldenfile::Mint;
+
+    let report_dir = PathBuf::from("tests/output");
+    let input_dir = PathBuf::from("tests/testdata");
+    let goldenfiles_dir = PathBuf::from("tests/goldenfiles");
+    for format in [ReportFormat::Csv, ReportFormat::Json] {
+        let rep_producer = ReportProducer::new(&report_dir, format, ReportOutput::ToFile).unwrap();
+        let _ = write_reports(
+            &rep_producer,
+            &[input_dir.clone()],
+            &ScanOptions::default(),
+            1,
+        );
+    }
+
+    match fs::read_dir(goldenfiles_dir.clone()) {
+        Ok(ok_goldenfile_dir) => {
+            let mut mint_dir = Mint::new(".");
+            for goldenfile in ok_goldenfile_dir.flatten() {
+                let p = goldenfile.path();
+                let ext: &str = p.extension().unwrap().to_str().unwrap();
+                if let Some(f) = p.file_name() {
+                    if let Some(f) = f.to_str() {
+                        let parts: Vec<&str> = f.split('_').collect();
+                        let computerName = parts[0];
+                        let reportType = parts[1];
+                        for entry in glob(
+                            format!("tests/output/{computerName}_{reportType}*.{ext}").as_str(),
+                        )
+                        .unwrap()
+                        {
+                            let entry = entry.unwrap();
+                            let mut golden_file = mint_dir.new_goldenfile(&p).unwrap();
+                            let new_file = fs::read_to_string(&entry).unwrap();
+                            let _ = writeln!(&mut golden_file, "{}", new_file);
+                            let _ = fs::remove_file(entry);
+                        }
+                    }
+                }
+            }
+        }
+        Err(_) => {
+            panic!("Failed to read goldenfiles directory.")
+        }
+    }
+}
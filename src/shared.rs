@@ -1,9 +1,112 @@
+use serde::Serialize;
 use simple_error::SimpleError;
+use std::fs::File;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::report::*;
+use crate::utils::filetime_to_datetime;
 use ese_parser_lib::parser::jet::DbState;
-use std::io::Write;
+use std::io::{Read, Write};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Called on Ctrl-C: the databases being read stop at the current record and their reports
+// are finalized as usual, the others are not started.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+const WELL_KNOWN_SIDS: &[(&str, &str)] = &[
+    ("S-1-0-0", "Nobody"),
+    ("S-1-1-0", "Everyone"),
+    ("S-1-2-0", "Local"),
+    ("S-1-3-0", "Creator Owner"),
+    ("S-1-3-1", "Creator Group"),
+    ("S-1-5-2", "Network"),
+    ("S-1-5-4", "Interactive"),
+    ("S-1-5-6", "Service"),
+    ("S-1-5-7", "Anonymous Logon"),
+    ("S-1-5-11", "Authenticated Users"),
+    ("S-1-5-18", "Local System"),
+    ("S-1-5-19", "Local Service"),
+    ("S-1-5-20", "Network Service"),
+    ("S-1-5-32-544", "Administrators"),
+    ("S-1-5-32-545", "Users"),
+    ("S-1-5-32-546", "Guests"),
+    ("S-1-5-32-547", "Power Users"),
+    ("S-1-15-2-1", "All Application Packages"),
+];
+
+// relative IDs of the well-known accounts and groups of a machine or domain (S-1-5-21-...)
+const WELL_KNOWN_RIDS: &[(&str, &str)] = &[
+    ("500", "Administrator"),
+    ("501", "Guest"),
+    ("503", "DefaultAccount"),
+    ("512", "Domain Admins"),
+    ("513", "Domain Users"),
+];
+
+// The name Windows gives a well-known SID, for --resolve-sids.
+pub fn well_known_sid_name(sid: &str) -> Option<&'static str> {
+    if let Some((_, name)) = WELL_KNOWN_SIDS.iter().find(|(s, _)| *s == sid) {
+        return Some(name);
+    }
+    let (domain, rid) = sid.rsplit_once('-')?;
+    if !domain.starts_with("S-1-5-21-") {
+        return None;
+    }
+    WELL_KNOWN_RIDS
+        .iter()
+        .find(|(r, _)| *r == rid)
+        .map(|(_, name)| *name)
+}
+
+// FILETIME columns; values that mean "no date" (see filetime_to_datetime) are left out.
+pub fn insert_filetime_val(r: &dyn Report, f: &str, ticks: u64) {
+    if let Some(dt) = filetime_to_datetime(ticks) {
+        r.insert_datetime_val(f, dt);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum DbKind {
+    Ese,
+    Sqlite,
+}
+
+// ESE file header carries 0x89ABCDEF (little-endian) right after the 4-byte checksum,
+// followed by the format version and the file type (0 - database, 1 - streaming file).
+// Checking the file type keeps checkpoint/streaming files next to Windows.edb out.
+const ESE_SIGNATURE_OFFSET: usize = 4;
+const ESE_SIGNATURE: [u8; 4] = [0xEF, 0xCD, 0xAB, 0x89];
+const ESE_FILE_TYPE_OFFSET: usize = 12;
+const ESE_FILE_TYPE_DATABASE: [u8; 4] = [0, 0, 0, 0];
+const SQLITE_SIGNATURE: &[u8; 16] = b"SQLite format 3\0";
+
+/// Sniffs the first bytes of a file to tell an ESE database from a SQLite one
+pub fn detect_db_type(path: &Path) -> Option<DbKind> {
+    let mut header = Vec::with_capacity(SQLITE_SIGNATURE.len());
+    File::open(path)
+        .ok()?
+        .take(SQLITE_SIGNATURE.len() as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+    if header.starts_with(SQLITE_SIGNATURE) {
+        Some(DbKind::Sqlite)
+    } else if header.get(ESE_SIGNATURE_OFFSET..ESE_SIGNATURE_OFFSET + 4) == Some(&ESE_SIGNATURE[..])
+        && header.get(ESE_FILE_TYPE_OFFSET..ESE_FILE_TYPE_OFFSET + 4)
+            == Some(&ESE_FILE_TYPE_DATABASE[..])
+    {
+        Some(DbKind::Ese)
+    } else {
+        None
+    }
+}
 
 type Reports = (
     Box<dyn Report>, /* file report */
@@ -11,6 +114,23 @@ type Reports = (
     Box<dyn Report>, /* act report */
 );
 
+// The report of a table dumped by --raw, named after the table.
+pub fn init_raw_report(
+    f: &Path,
+    report_prod: &ReportProducer,
+    recovered_hostname: &str,
+    table: &str,
+    status_logger: &mut Box<dyn Write>,
+    edb_database_state: Option<DbState>,
+) -> Result<Box<dyn Report>, SimpleError> {
+    let (paths, rep) = report_prod.new_report(f, recovered_hostname, table, edb_database_state)?;
+    for path in paths {
+        writeln!(status_logger, "{}", path.to_string_lossy())
+            .map_err(|e| SimpleError::new(format!("{e}")))?;
+    }
+    Ok(rep)
+}
+
 pub fn init_reports(
     f: &Path,
     report_prod: &ReportProducer,
@@ -18,30 +138,47 @@ pub fn init_reports(
     status_logger: &mut Box<dyn Write>,
     edb_database_state: Option<DbState>,
 ) -> Result<Reports, SimpleError> {
-    let (file_rep_path, file_rep) =
-        report_prod.new_report(f, recovered_hostname, "File_Report", edb_database_state)?;
-
-    let (ie_rep_path, ie_rep) = report_prod.new_report(
-        f,
-        recovered_hostname,
-        "Internet_History_Report",
-        edb_database_state,
-    )?;
-
-    let (act_rep_path, act_rep) = report_prod.new_report(
-        f,
-        recovered_hostname,
-        "Activity_History_Report",
-        edb_database_state,
-    )?;
-
-    writeln!(
-        status_logger,
-        "{}\n{}\n{}\n",
-        file_rep_path.to_string_lossy(),
-        ie_rep_path.to_string_lossy(),
-        act_rep_path.to_string_lossy()
-    )
-    .map_err(|e| SimpleError::new(format!("{e}")))?;
+    let mut rep_paths = Vec::new();
+    // reports not selected with --reports still receive their records, so the other
+    // reports don't, but nothing is written
+    let mut new_report =
+        |kind: ReportKind, report_suffix: &str| -> Result<Box<dyn Report>, SimpleError> {
+            if !report_prod.is_report_enabled(kind) {
+                return Ok(Box::new(ReportNull));
+            }
+            // there are no files to list in stdout mode
+            let (paths, rep) =
+                report_prod.new_report(f, recovered_hostname, report_suffix, edb_database_state)?;
+            rep_paths.extend(paths);
+            Ok(rep)
+        };
+    let file_rep = new_report(ReportKind::File, "File_Report")?;
+    let ie_rep = new_report(ReportKind::Internet, "Internet_History_Report")?;
+    let act_rep = new_report(ReportKind::Activity, "Activity_History_Report")?;
+
+    for path in &rep_paths {
+        writeln!(status_logger, "{}", path.to_string_lossy())
+            .map_err(|e| SimpleError::new(format!("{e}")))?;
+    }
+    if !rep_paths.is_empty() {
+        writeln!(status_logger).map_err(|e| SimpleError::new(format!("{e}")))?;
+    }
     Ok((file_rep, ie_rep, act_rep))
 }
+
+#[test]
+fn test_detect_db_type() {
+    assert_eq!(
+        detect_db_type(Path::new("tests/testdata/Windows.edb")),
+        Some(DbKind::Ese)
+    );
+    assert_eq!(
+        detect_db_type(Path::new("tests/testdata/Windows.db")),
+        Some(DbKind::Sqlite)
+    );
+    assert_eq!(detect_db_type(Path::new("Cargo.toml")), None);
+    assert_eq!(
+        detect_db_type(Path::new("tests/testdata/missing.edb")),
+        None
+    );
+}
@@ -1,13 +1,16 @@
 use itertools::Itertools;
+use log::{error, info, warn};
 use simple_error::SimpleError;
 use std::collections::HashMap;
 use std::path::Path;
 
+use crate::record::{Record, RecordCollector};
 use crate::report::*;
 use crate::shared::*;
 use crate::utils::*;
 
 use ese_parser_lib::ese_parser::FromBytes;
+use owning_ref::OwningHandle;
 use sqlite::State;
 use std::io::Write;
 
@@ -34,13 +37,117 @@ fn sqlite_get_hostname(c: &sqlite::Connection) -> Result<String, SimpleError> {
     let mut s = map_err!(c.prepare(q))?;
     if let Ok(State::Row) = s.next() {
         let val = map_err!(s.read::<Vec<u8>, _>("Value"))?;
-        return Ok(String::from_utf8_lossy(&val).into_owned());
+        return Ok(from_utf8(&val));
     }
     Err(SimpleError::new(
         "Empty field System.ComputerName".to_string(),
     ))
 }
 
+fn sqlite_hostname_or_unknown(c: &sqlite::Connection) -> String {
+    match sqlite_get_hostname(c) {
+        Ok(h) => h,
+        Err(e) => {
+            warn!(
+                "sqlite_get_hostname() failed: {e}. Will use '{UNKNOWN_HOSTNAME}' as a hostname."
+            );
+            UNKNOWN_HOSTNAME.to_string()
+        }
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// Tables of the database with their columns, for --list-tables.
+pub fn sqlite_list_tables(f: &Path) -> Result<Vec<(String, Vec<String>)>, SimpleError> {
+    let c = map_err!(sqlite::Connection::open_with_flags(
+        f,
+        sqlite::OpenFlags::new().set_read_only()
+    ))?;
+    sqlite_tables(&c)
+}
+
+fn sqlite_tables(c: &sqlite::Connection) -> Result<Vec<(String, Vec<String>)>, SimpleError> {
+    let q = "select name from sqlite_master where type = 'table' order by name";
+    let mut tables = Vec::new();
+    for row in map_err!(c.prepare(q))?.into_iter() {
+        let name = map_err!(row)?.read::<&str, _>("name").to_string();
+        let q = format!("pragma table_info({})", quote_identifier(&name));
+        let mut columns = Vec::new();
+        for row in map_err!(c.prepare(q))?.into_iter() {
+            columns.push(map_err!(row)?.read::<&str, _>("name").to_string());
+        }
+        tables.push((name, columns));
+    }
+    Ok(tables)
+}
+
+// --raw: every column of every row of the selected tables, one report per table.
+pub fn sqlite_dump_raw(
+    f: &Path,
+    report_prod: &ReportProducer,
+    status_logger: &mut Box<dyn Write>,
+) -> Result<(), SimpleError> {
+    info!("Dumping SQLite db: {}", &f.to_string_lossy());
+    let c = map_err!(sqlite::Connection::open_with_flags(
+        f,
+        sqlite::OpenFlags::new().set_read_only()
+    ))?;
+    let recovered_hostname = sqlite_hostname_or_unknown(&c);
+    for (t, columns) in sqlite_tables(&c)? {
+        if !report_prod.is_raw_table(&t) {
+            continue;
+        }
+        let mut rep =
+            init_raw_report(f, report_prod, &recovered_hostname, &t, status_logger, None)?;
+        for col in &columns {
+            rep.set_field(col);
+        }
+        let mut s = map_err!(c.prepare(format!("select * from {}", quote_identifier(&t))))?;
+        loop {
+            if is_interrupted() {
+                warn!(
+                    "Interrupted while reading '{}'. Its reports are partial.",
+                    f.to_string_lossy()
+                );
+                report_prod.mark_partial(f);
+                break;
+            }
+            if rep.is_full() {
+                break;
+            }
+            match s.next() {
+                Ok(State::Row) => {}
+                Ok(State::Done) => break,
+                Err(e) => {
+                    error!(
+                        "Failed to read '{}': {e}. Its reports are partial.",
+                        f.to_string_lossy()
+                    );
+                    report_prod.mark_partial(f);
+                    break;
+                }
+            }
+            rep.create_new_row();
+            for (i, col) in columns.iter().enumerate() {
+                match s.read::<sqlite::Value, _>(i) {
+                    Ok(sqlite::Value::Integer(n)) => rep.insert_i64_val(col, n),
+                    Ok(sqlite::Value::Float(v)) => rep.insert_float_val(col, v),
+                    Ok(sqlite::Value::String(v)) => rep.insert_str_val(col, v),
+                    Ok(sqlite::Value::Binary(v)) => rep.insert_blob_val(col, &v),
+                    Ok(sqlite::Value::Null) => {}
+                    Err(e) => warn!("Error while getting column {col} from {t}: {e}"),
+                }
+            }
+        }
+        rep.create_new_row();
+        rep.flush();
+    }
+    Ok(())
+}
+
 fn populate_property_id_maps<'a>(
     c: &sqlite::Connection,
     idToProp: &'a mut HashMap<i64, (String, i64)>,
@@ -67,12 +174,7 @@ pub fn sqlite_generate_report(
     report_prod: &ReportProducer,
     status_logger: &mut Box<dyn Write>,
 ) -> Result<(), SimpleError> {
-    writeln!(
-        status_logger,
-        "Processing SQLite db: {}",
-        &f.to_string_lossy()
-    )
-    .map_err(|e| SimpleError::new(format!("{e}")))?;
+    info!("Processing SQLite db: {}", &f.to_string_lossy());
 
     let c = map_err!(sqlite::Connection::open_with_flags(
         f,
@@ -81,13 +183,7 @@ pub fn sqlite_generate_report(
     let query = "select * from SystemIndex_1_PropertyStore";
     let mut s = map_err!(c.prepare(query))?;
 
-    let recovered_hostname = match sqlite_get_hostname(&c) {
-        Ok(h) => h,
-        Err(e) => {
-            eprintln!("sqlite_get_hostname() failed: {e}. Will use 'Unknown' as a hostname.");
-            "Unknown".to_string()
-        }
-    };
+    let recovered_hostname = sqlite_hostname_or_unknown(&c);
 
     let (mut file_rep, mut ie_rep, mut act_rep) =
         init_reports(f, report_prod, &recovered_hostname, status_logger, None)?;
@@ -101,36 +197,159 @@ pub fn sqlite_generate_report(
     let mut handler = |workId: u32, record: &mut HashMap<i64, Vec<u8>>| {
         // new WorkId, handle all collected fields
         if !record.is_empty() {
-            if is_internet_record(&record, &propNameToId).is_ok() {
-                write_record_to_report(record, workId, &idToProp, &mut *ie_rep);
-            } else if is_activity_history_record(record, &propNameToId).is_ok() {
-                write_record_to_report(record, workId, &idToProp, &mut *act_rep);
-            } else {
-                write_record_to_report(record, workId, &idToProp, &mut *file_rep);
-            }
+            let rep = match record_kind(record, &propNameToId) {
+                ReportKind::Internet => &mut ie_rep,
+                ReportKind::Activity => &mut act_rep,
+                ReportKind::File => &mut file_rep,
+            };
+            write_record_to_report(record, workId, &idToProp, &mut **rep);
             record.clear();
         }
+        file_rep.is_full() && ie_rep.is_full() && act_rep.is_full()
     };
 
     let mut record = HashMap::new();
     let mut workId_current = 0;
-    while let Ok(State::Row) = s.next() {
-        let workId = map_err!(s.read::<i64, _>("WorkId"))? as u32;
+    loop {
+        if is_interrupted() {
+            warn!(
+                "Interrupted while reading '{}'. Its reports are partial.",
+                f.to_string_lossy()
+            );
+            report_prod.mark_partial(f);
+            break;
+        }
+        let (workId, columnId, value) = match read_property_row(&mut s) {
+            Ok(Some(row)) => row,
+            Ok(None) => break,
+            Err(e) => {
+                // keep what was read so far, the reports are finalized when dropped
+                error!(
+                    "Failed to read '{}': {e}. Its reports are partial.",
+                    f.to_string_lossy()
+                );
+                report_prod.mark_partial(f);
+                break;
+            }
+        };
         if workId_current != workId {
-            handler(workId_current, &mut record);
+            if handler(workId_current, &mut record) {
+                break;
+            }
             workId_current = workId;
         }
-        let columnId = map_err!(s.read::<i64, _>("ColumnId"))?;
-        let value = map_err!(s.read::<Vec<u8>, _>("Value"))?;
         record.insert(columnId, value);
     }
     // handle last element
     if !record.is_empty() {
         handler(workId_current, &mut record);
     }
+    for rep in [&mut file_rep, &mut ie_rep, &mut act_rep] {
+        rep.create_new_row();
+        rep.flush();
+    }
     Ok(())
 }
 
+type PropertyRows = OwningHandle<Box<sqlite::Connection>, Box<sqlite::Statement<'static>>>;
+
+// The records sqlite_records reads, a WorkId at a time.
+pub struct SqliteRecords {
+    rows: PropertyRows,
+    id_to_prop: HashMap<i64, (String, i64)>,
+    prop_name_to_id: HashMap<String, i64>,
+    work_id: u32,
+    record: HashMap<i64, Vec<u8>>, // properties of work_id read so far
+    done: bool,
+}
+
+// The records of the reports of a Windows.db, as sqlite_generate_report writes them
+// but without any ReportProducer options applied.
+pub fn sqlite_records(f: &Path) -> Result<SqliteRecords, SimpleError> {
+    let c = map_err!(sqlite::Connection::open_with_flags(
+        f,
+        sqlite::OpenFlags::new().set_read_only()
+    ))?;
+    let mut id_to_prop = HashMap::new();
+    let mut prop_name_to_id = HashMap::new();
+    populate_property_id_maps(&c, &mut id_to_prop, &mut prop_name_to_id)?;
+    // the statement borrows the connection it is kept with, as in SqlReader
+    let rows = PropertyRows::try_new(Box::new(c), |c| unsafe {
+        (*c).prepare("select * from SystemIndex_1_PropertyStore")
+            .map(Box::new)
+    });
+    Ok(SqliteRecords {
+        rows: map_err!(rows)?,
+        id_to_prop,
+        prop_name_to_id,
+        work_id: 0,
+        record: HashMap::new(),
+        done: false,
+    })
+}
+
+impl SqliteRecords {
+    fn take_record(&mut self) -> Option<Record> {
+        if self.record.is_empty() {
+            return None;
+        }
+        let mut r = RecordCollector::default();
+        write_record_to_report(&self.record, self.work_id, &self.id_to_prop, &mut r);
+        let kind = record_kind(&self.record, &self.prop_name_to_id);
+        self.record.clear();
+        Some(r.into_record(kind))
+    }
+}
+
+impl Iterator for SqliteRecords {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Record> {
+        while !self.done {
+            match read_property_row(&mut self.rows) {
+                Ok(Some((work_id, column_id, value))) => {
+                    let record = if work_id != self.work_id {
+                        self.take_record()
+                    } else {
+                        None
+                    };
+                    self.work_id = work_id;
+                    self.record.insert(column_id, value);
+                    if record.is_some() {
+                        return record;
+                    }
+                }
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    error!("Failed to read the next record: {e}");
+                    self.done = true;
+                }
+            }
+        }
+        self.take_record()
+    }
+}
+
+fn read_property_row(
+    s: &mut sqlite::Statement,
+) -> Result<
+    Option<(
+        u32,     /*WorkId*/
+        i64,     /*ColumnId*/
+        Vec<u8>, /*Value*/
+    )>,
+    SimpleError,
+> {
+    match map_err!(s.next())? {
+        State::Row => Ok(Some((
+            map_err!(s.read::<i64, _>("WorkId"))? as u32,
+            map_err!(s.read::<i64, _>("ColumnId"))?,
+            map_err!(s.read::<Vec<u8>, _>("Value"))?,
+        ))),
+        State::Done => Ok(None),
+    }
+}
+
 fn write_record_to_report(
     record: &HashMap<i64, Vec<u8>>,
     workId: u32,
@@ -147,26 +366,42 @@ fn write_record_to_report(
             match storage_type {
                 11 => {
                     // inferred to be string type
-                    report.insert_str_val(&property_name, String::from_utf8_lossy(val).into_owned())
+                    report.insert_str_val(&property_name, from_utf8(val))
                 }
                 12 => {
                     // inferred to be date type when "Date" present in property name
                     if property_name.contains("Date") || property_name.contains("Time") {
-                        report.insert_str_val(
-                            &property_name,
-                            format_date_time(get_date_time_from_filetime(u64::from_bytes(val))),
-                        )
+                        insert_filetime_val(report, &property_name, u64::from_bytes(val))
                     } else {
                         // otherwise inferred to be int type
                         report.insert_int_val(&property_name, u64::from_bytes(val))
                     }
                 }
-                _ => { /* Storage type not supported. */ }
+                _ if is_sid_column(&property_name) && is_sid(val) => {
+                    report.insert_sid_val(&property_name, val)
+                }
+                _ => {
+                    // binary or not supported, encoded as --blob-encoding says (base64 by default)
+                    report.insert_blob_val(&property_name, val)
+                }
             }
         }
     }
 }
 
+fn record_kind(
+    record: &HashMap<i64 /*ColumnId*/, Vec<u8> /*Value*/>,
+    propNameToId: &HashMap<String, i64>,
+) -> ReportKind {
+    if is_internet_record(record, propNameToId).is_ok() {
+        ReportKind::Internet
+    } else if is_activity_history_record(record, propNameToId).is_ok() {
+        ReportKind::Activity
+    } else {
+        ReportKind::File
+    }
+}
+
 fn is_internet_record(
     record: &HashMap<i64 /*ColumnId*/, Vec<u8> /*Value*/>,
     propNameToId: &HashMap<String, i64>,
@@ -219,3 +454,73 @@ fn test_get_property_id_map() {
     assert!(idToProp.len() == 597);
     assert!(PropNameToId.len() == idToProp.len());
 }
+
+#[test]
+fn test_sqlite_dump_raw() {
+    use tempdir::TempDir;
+
+    let dir = TempDir::new("sidr_raw").unwrap();
+    let report_prod = ReportProducer::new(dir.path(), ReportFormat::Csv, ReportOutput::ToFile)
+        .unwrap()
+        .with_raw_tables(vec![glob::Pattern::new("*_Metadata").unwrap()]);
+    let mut status_logger: Box<dyn Write> = Box::new(std::io::sink());
+    sqlite_dump_raw(
+        Path::new("tests/testdata/Windows.db"),
+        &report_prod,
+        &mut status_logger,
+    )
+    .unwrap();
+    let summary = report_prod.summary();
+    assert_eq!(summary.len(), 1);
+    assert_eq!(summary[0].report, "SystemIndex_1_PropertyStore_Metadata");
+    assert_eq!(summary[0].records, 597);
+    let data = std::fs::read_to_string(summary[0].path.as_ref().unwrap()).unwrap();
+    let header = data.lines().next().unwrap();
+    assert!(header.split(',').any(|f| f == "StorageType"), "{header}");
+}
+
+#[test]
+fn test_sqlite_records() {
+    use crate::record::Value;
+
+    let f = Path::new("tests/testdata/Windows.db");
+    let records: Vec<Record> = sqlite_records(f).unwrap().collect();
+    assert!(records
+        .iter()
+        .all(|r| matches!(r.values.first(), Some((f, Value::Int(_))) if f == "WorkId")));
+    let internet = records
+        .iter()
+        .filter(|r| r.kind == ReportKind::Internet)
+        .count();
+    assert!(internet > 0);
+
+    // the same records as the reports get
+    let dir = tempdir::TempDir::new("sidr_records").unwrap();
+    let report_prod = ReportProducer::new(dir.path(), ReportFormat::Json, ReportOutput::ToFile)
+        .unwrap()
+        .with_count_only(true);
+    let mut status_logger: Box<dyn Write> = Box::new(std::io::sink());
+    sqlite_generate_report(f, &report_prod, &mut status_logger).unwrap();
+    let summary = report_prod.summary();
+    assert_eq!(
+        summary.iter().map(|s| s.records).sum::<usize>(),
+        records.len()
+    );
+    let internet_summary = summary
+        .iter()
+        .find(|s| s.report == "Internet_History_Report")
+        .unwrap();
+    assert_eq!(internet_summary.records, internet);
+}
+
+#[test]
+fn test_sqlite_list_tables() {
+    let tables = sqlite_list_tables(Path::new("tests/testdata/Windows.db")).unwrap();
+    let (_, columns) = tables
+        .iter()
+        .find(|(name, _)| name == "SystemIndex_1_PropertyStore")
+        .unwrap();
+    for c in ["WorkId", "ColumnId", "Value"] {
+        assert!(columns.iter().any(|n| n == c), "{columns:?}");
+    }
+}
@@ -1,6 +1,7 @@
 use chrono::prelude::*;
 
 use bitflags::bitflags;
+use log::debug;
 use std::convert::TryInto;
 
 /// Converts a u64 filetime to a DateTime<Utc>
@@ -20,6 +21,65 @@ pub fn get_date_time_from_filetime(filetime: u64) -> DateTime<Utc> {
     )
 }
 
+/// Converts FILETIME ticks (100ns since 1601-01-01) to a DateTime<Utc>; 0 and the
+/// 0x7FFF.../0xFFFF... "never" values, as well as dates chrono can't represent, are None
+pub fn filetime_to_datetime(ticks: u64) -> Option<DateTime<Utc>> {
+    const TICKS_PER_SECOND: u64 = 10_000_000;
+    const UNIX_EPOCH_SECONDS_SINCE_WINDOWS_EPOCH: i64 = 11644473600;
+    if ticks == 0 || ticks >= i64::MAX as u64 {
+        return None;
+    }
+    let secs = (ticks / TICKS_PER_SECOND) as i64 - UNIX_EPOCH_SECONDS_SINCE_WINDOWS_EPOCH;
+    let nanos = (ticks % TICKS_PER_SECOND) as u32 * 100;
+    Utc.timestamp_opt(secs, nanos).single()
+}
+
+#[test]
+fn filetime_to_datetime_test() {
+    let unix_epoch = Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap();
+    assert_eq!(filetime_to_datetime(116444736000000000), Some(unix_epoch));
+    assert_eq!(
+        filetime_to_datetime(116444736000000001).map(format_date_time),
+        Some("1970-01-01T00:00:00.0000001Z".to_string())
+    );
+    assert_eq!(
+        filetime_to_datetime(1).map(format_date_time),
+        Some("1601-01-01T00:00:00.0000001Z".to_string())
+    );
+    assert_eq!(
+        filetime_to_datetime(133226359641234567),
+        Some(get_date_time_from_filetime(133226359641234567))
+    );
+    assert_eq!(filetime_to_datetime(0), None);
+    assert_eq!(filetime_to_datetime(0x7FFF_FFFF_FFFF_FFFF), None);
+    assert_eq!(filetime_to_datetime(u64::MAX), None);
+}
+
+/// Converts an OLE automation date (days since 1899-12-30, the fraction is the time of day
+/// even for negative dates) to a DateTime<Utc>; 0 (unset), NaN and dates past 9999 are None
+pub fn ole_date_to_datetime(days: f64) -> Option<DateTime<Utc>> {
+    const MS_PER_DAY: f64 = 86_400_000.0;
+    const MAX_DAYS: f64 = 2_958_466.0; // 10000-01-01
+    if days == 0.0 || !days.is_finite() || days.abs() >= MAX_DAYS {
+        return None;
+    }
+    let whole = days.trunc();
+    let ms = whole * MS_PER_DAY + (days - whole).abs() * MS_PER_DAY;
+    let ole_epoch = Utc.with_ymd_and_hms(1899, 12, 30, 0, 0, 0).unwrap();
+    ole_epoch.checked_add_signed(chrono::Duration::milliseconds(ms.round() as i64))
+}
+
+#[test]
+fn ole_date_to_datetime_test() {
+    let ymd_hms = |y, m, d, h| Utc.with_ymd_and_hms(y, m, d, h, 0, 0).single();
+    assert_eq!(ole_date_to_datetime(25569.0), ymd_hms(1970, 1, 1, 0));
+    assert_eq!(ole_date_to_datetime(45000.5), ymd_hms(2023, 3, 15, 12));
+    assert_eq!(ole_date_to_datetime(-1.25), ymd_hms(1899, 12, 29, 6));
+    assert_eq!(ole_date_to_datetime(0.0), None);
+    assert_eq!(ole_date_to_datetime(f64::NAN), None);
+    assert_eq!(ole_date_to_datetime(1e300), None);
+}
+
 /// Converts a DateTime<Utc> to ISO-8601/RFC-3339 format `%Y-%m-%dT%H:%M:%S%.7f` (manually, since Rust doesn't support `%.7f`)
 pub fn format_date_time(date_time: DateTime<Utc>) -> String {
     let fractional_seconds = date_time.format("%9f").to_string();
@@ -42,6 +102,19 @@ pub fn format_date_time(date_time: DateTime<Utc>) -> String {
     date_time.to_rfc3339_opts(SecondsFormat::Nanos, true)
 }
 
+/// Same as format_date_time, but with the UTC offset of the date time's zone, e.g. `2023-03-06T20:52:44.0000000-05:00`
+pub fn format_date_time_offset<Tz: TimeZone>(date_time: &DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    format!(
+        "{}.{:07}{}",
+        date_time.format("%Y-%m-%dT%H:%M:%S"),
+        date_time.nanosecond() / 100,
+        date_time.format("%:z")
+    )
+}
+
 // extract GUID string from string like:
 // file:///C:/Users/testuser/Desktop/Test-Word.docx?VolumeId={AC048C6D-1E3C-4B21-B20D-75745DD788B3}&ObjectId={5E5EFB20-A904-11ED-A0EA-DC215CBBECEC}&KnownFolderId=ThisPCDesktopFolder&KnownFolderLength=25
 pub fn find_guid(inp: &str, v: &str) -> String {
@@ -55,12 +128,89 @@ pub fn find_guid(inp: &str, v: &str) -> String {
     s
 }
 
+// Invalid text (e.g. in carved records) is repaired with U+FFFD rather than failing the report.
 pub fn from_utf16(val: &[u8]) -> String {
     let s: Vec<u16> = val
         .chunks_exact(2)
         .map(|a| u16::from_ne_bytes([a[0], a[1]]))
         .collect();
-    String::from_utf16_lossy(s.as_slice())
+    String::from_utf16(s.as_slice()).unwrap_or_else(|_| {
+        debug!("Invalid UTF-16 text {val:02x?}, replaced the bad characters");
+        String::from_utf16_lossy(s.as_slice())
+    })
+}
+
+pub fn from_utf8(val: &[u8]) -> String {
+    match std::str::from_utf8(val) {
+        Ok(s) => s.to_string(),
+        Err(_) => {
+            debug!("Invalid UTF-8 text {val:02x?}, replaced the bad characters");
+            String::from_utf8_lossy(val).into_owned()
+        }
+    }
+}
+
+#[test]
+fn from_utf_lossy_test() {
+    assert_eq!(from_utf8(b"ok"), "ok");
+    assert_eq!(from_utf8(b"a\xffb"), "a\u{fffd}b");
+    let utf16: Vec<u8> = [0x61u16, 0xd800, 0x62]
+        .iter()
+        .flat_map(|c| c.to_ne_bytes())
+        .collect();
+    assert_eq!(from_utf16(&utf16), "a\u{fffd}b");
+}
+
+// Binary SID: revision, sub-authority count, 48-bit big-endian identifier authority and
+// the little-endian 32-bit sub-authorities.
+pub fn is_sid(bytes: &[u8]) -> bool {
+    bytes.len() >= 8 && bytes[0] == 1 && bytes.len() == 8 + 4 * bytes[1] as usize
+}
+
+// The S-1-5-21-... form of a binary SID (see is_sid).
+pub fn sid_to_string(bytes: &[u8]) -> String {
+    let authority = bytes
+        .iter()
+        .skip(2)
+        .take(6)
+        .fold(0u64, |a, b| (a << 8) | *b as u64);
+    let mut s = match authority {
+        // as ConvertSidToStringSid writes authorities that don't fit 32 bits
+        a if a >> 32 != 0 => format!("S-{}-0x{a:012X}", bytes[0]),
+        a => format!("S-{}-{a}", bytes[0]),
+    };
+    for sub in bytes.get(8..).unwrap_or_default().chunks_exact(4) {
+        s.push_str(&format!("-{}", u32::from_le_bytes(sub.try_into().unwrap())));
+    }
+    s
+}
+
+// Columns holding SIDs, e.g. System_Security_OwnerSid or RequiredSIDs.
+pub fn is_sid_column(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with("sid") || name.ends_with("sids")
+}
+
+#[test]
+fn sid_to_string_test() {
+    let sid = [
+        0x01, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x15, 0x00, 0x00, 0x00, 0xc7, 0xf7, 0xfe,
+        0xd7, 0x7c, 0x77, 0x55, 0xc8, 0x94, 0x5a, 0xce, 0x01, 0xf5, 0x03, 0x00, 0x00,
+    ];
+    assert!(is_sid(&sid));
+    assert_eq!(
+        sid_to_string(&sid),
+        "S-1-5-21-3623811015-3361044348-30300820-1013"
+    );
+    let local_system = [
+        0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x12, 0x00, 0x00, 0x00,
+    ];
+    assert!(is_sid(&local_system));
+    assert_eq!(sid_to_string(&local_system), "S-1-5-18");
+    assert!(!is_sid(&sid[..20]));
+    assert!(!is_sid(&[]));
+    assert!(is_sid_column("System_Security_OwnerSid"));
+    assert!(!is_sid_column("System_Search_Inside"));
 }
 
 bitflags! {
@@ -144,6 +294,59 @@ fn json_escape_test() {
     }
 }
 
+#[test]
+fn json_escape_control_chars_test() {
+    let input: String = (0u8..0x20).map(char::from).collect();
+    let mut expected = String::from("\"");
+    for c in input.chars() {
+        match c {
+            '\x08' => expected.push_str("\\b"),
+            '\t' => expected.push_str("\\t"),
+            '\n' => expected.push_str("\\n"),
+            '\x0C' => expected.push_str("\\f"),
+            '\r' => expected.push_str("\\r"),
+            _ => expected.push_str(&format!("\\u{:04x}", c as u32)),
+        }
+    }
+    expected.push('"');
+    let escaped = json_escape(&input);
+    assert_eq!(escaped, expected);
+    assert_eq!(serde_json::from_str::<String>(&escaped).unwrap(), input);
+}
+
+pub fn xml_escape(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // control characters other than tab/newline/carriage return are not allowed in XML 1.0
+            '\t' | '\n' | '\r' => out.push(c),
+            '\u{0}'..='\u{1f}' => out.push('\u{FFFD}'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[test]
+fn xml_escape_test() {
+    let tests = &[
+        ("", ""),
+        ("test", "test"),
+        ("<a href=\"x\">", "&lt;a href=&quot;x&quot;&gt;"),
+        ("Tom & Jerry's", "Tom &amp; Jerry&apos;s"),
+        ("t\nt\tt", "t\nt\tt"),
+        ("\x08", "\u{FFFD}"),
+    ];
+    for i in tests {
+        assert_eq!(xml_escape(i.0), i.1);
+    }
+}
+
 pub struct DropMe<F>
 where
     F: Fn(),